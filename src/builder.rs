@@ -0,0 +1,414 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use log::{Level, LevelFilter};
+use regex::Regex;
+use crate::{BioLogger, Entry, Error, LoggerGuard};
+use crate::filter::{regex_error, Directives, Filter, MatchMode};
+use crate::color::{ColorChoice, Stream};
+use crate::config;
+use crate::format::{Format, Template};
+use crate::handle::{Config, Shared, SinkDefaults};
+use crate::output::{Output, Sink, Sinks};
+use crate::panic::{self, PanicSettings};
+use crate::timestamp::{self, Timestamp};
+use crate::worker::{LogWorker, Overflow, QUEUE_CAPACITY};
+
+/// Configures a [`BioLogger`]. Created with [`BioLogger::builder`].
+///
+/// Example use:
+/// ```no_run
+/// let logger = biologischer_log::BioLogger::builder()
+///     .whitelist_module(env!("CARGO_CRATE_NAME"))
+///     .level(log::LevelFilter::Debug)
+///     .init();
+/// ```
+pub struct Builder {
+    level: Option<LevelFilter>,
+    directives: Vec<(String, Option<LevelFilter>)>,
+    match_mode: MatchMode,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    errors: Vec<String>,
+    output: Output,
+    sinks: Vec<Sink>,
+    color: ColorChoice,
+    timestamp: Timestamp,
+    /// `None` picks the default template, with or without a thread column.
+    format: Option<Format>,
+    show_thread: bool,
+    panic_hook: bool,
+    chain_panic_hook: bool,
+    log_panics: bool,
+    env_var: Option<String>,
+    config_file: Option<PathBuf>,
+    overflow: Overflow,
+    queue_capacity: usize,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder {
+            level: None,
+            directives: Vec::new(),
+            match_mode: MatchMode::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            errors: Vec::new(),
+            output: Output::Stdout,
+            sinks: Vec::new(),
+            color: ColorChoice::default(),
+            timestamp: Timestamp::default(),
+            format: None,
+            show_thread: false,
+            panic_hook: true,
+            chain_panic_hook: true,
+            log_panics: true,
+            env_var: Some("BIO_LOG".to_string()),
+            config_file: None,
+            overflow: Overflow::default(),
+            queue_capacity: QUEUE_CAPACITY,
+        }
+    }
+}
+
+impl Builder {
+    /// Set the maximum level of whitelisted modules without a level of their own.
+    /// The environment variable still takes precedence.
+    /// Defaults to `Trace` in debug builds and `Info` in release builds.
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Allow logs from this module and all of its submodules at the global level.
+    /// `!module` denies it instead, see [`deny_module`](Builder::deny_module).
+    ///
+    /// The module can be a glob: `*` and `?` match within one path segment, `**` across segments
+    /// and `{a,b}` either alternative, e.g. `*::sounds`, `mycrate::deserialize::*::parse_*` or `{tokio,hyper}`.
+    pub fn whitelist_module(mut self, module: &str) -> Self {
+        self.directives.push((module.to_string(), None));
+        self
+    }
+
+    /// Mute this module and all of its submodules, even if a parent module is whitelisted,
+    /// e.g. `rocket::server` while the rest of `rocket` keeps logging. Short for `module_level(module, LevelFilter::Off)`.
+    pub fn deny_module(self, module: &str) -> Self {
+        self.module_level(module, LevelFilter::Off)
+    }
+
+    /// Allow logs from this module and all of its submodules up to `level`, regardless of the global level.
+    /// The most specific module wins, e.g. `mycrate` at `Trace` and `mycrate::deserialize` at `Info`.
+    pub fn module_level(mut self, module: &str, level: LevelFilter) -> Self {
+        self.directives.push((module.to_string(), Some(level)));
+        self
+    }
+
+    /// Also match the whitelist against the module path or source file of a record, not only its target.
+    /// With [`MatchMode::File`], entries containing a `/` like `src/audio`, or starting with `file:`, match source files.
+    /// In the environment variable they always need the `file:` prefix, e.g. `file:src/audio=trace`.
+    pub fn match_mode(mut self, mode: MatchMode) -> Self {
+        self.match_mode = mode;
+        self
+    }
+
+    /// Only log messages whose text matches this regex.
+    /// With several patterns, a message has to match any one of them.
+    /// An invalid regex is reported with a warning once the logger starts.
+    pub fn include_messages(mut self, pattern: &str) -> Self {
+        match Regex::new(pattern) {
+            Ok(regex) => self.include.push(regex),
+            Err(error) => self.errors.push(format!("Ignoring invalid message pattern `{pattern}`: {}", regex_error(&error))),
+        }
+        self
+    }
+
+    /// Don't log messages whose text matches this regex.
+    /// An invalid regex is reported with a warning once the logger starts.
+    pub fn exclude_messages(mut self, pattern: &str) -> Self {
+        match Regex::new(pattern) {
+            Ok(regex) => self.exclude.push(regex),
+            Err(error) => self.errors.push(format!("Ignoring invalid message pattern `{pattern}`: {}", regex_error(&error))),
+        }
+        self
+    }
+
+    /// Add directives in the same syntax as the environment variable,
+    /// e.g. `info,mycrate::sounds=trace,rocket=off/pattern`.
+    /// Malformed pieces are reported with a warning once the logger starts.
+    pub fn parse_filters(mut self, spec: &str) -> Self {
+        let directives = Directives::parse(spec);
+        if directives.level.is_some() {
+            self.level = directives.level;
+        }
+        self.directives.extend(directives.modules);
+        self.include.extend(directives.include);
+        self.exclude.extend(directives.exclude);
+        self.errors.extend(directives.errors.into_iter().map(|error| format!("Ignoring malformed filter directive {error}")));
+        self
+    }
+
+    /// Set where logs are written to if no sinks are added. Defaults to stdout.
+    /// Use [`Output::Split`] to send warnings and errors to stderr instead.
+    pub fn output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Write records to this sink too. Once a sink is added, [`Builder::output`] is ignored.
+    pub fn sink(mut self, sink: Sink) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Set the color choice of all sinks that don't have their own and of the panic banner.
+    /// Defaults to [`ColorChoice::Auto`], coloring only terminals.
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Set how timestamps are written by all sinks that don't have their own,
+    /// e.g. [`Timestamp::Rfc3339`] or [`Timestamp::None`] under systemd.
+    /// Defaults to the local time as `%H:%M:%S%.3f`.
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Write the local time with this `chrono` strftime pattern, short for [`Timestamp::Local`].
+    pub fn timestamp_format(self, format: &str) -> Self {
+        self.timestamp(Timestamp::Local(format.to_string()))
+    }
+
+    /// Set how records are turned into lines for all sinks that don't have their own, e.g. [`Format::Json`].
+    /// `text`, `json` or `logfmt` in the `<env var>_FORMAT` environment variable, e.g. `BIO_LOG_FORMAT`, takes precedence.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Write human-readable lines laid out by this template, see [`Template`].
+    pub fn template(self, template: Template) -> Self {
+        self.format(Format::Text(template))
+    }
+
+    /// Add a column with the name (or ID) of the logging thread to the default template.
+    /// Has no effect on sinks with their own format; use `{thread}` in a [`Template`] there.
+    pub fn show_thread(mut self, enabled: bool) -> Self {
+        self.show_thread = enabled;
+        self
+    }
+
+    /// Whether to replace the panic hook with the logger's panic banner. Enabled by default.
+    /// Messages still waiting in the queue are written before the banner, unless a sink is stuck for over a second.
+    pub fn panic_hook(mut self, enabled: bool) -> Self {
+        self.panic_hook = enabled;
+        self
+    }
+
+    /// Whether the panic hook calls the hook that was installed before it, e.g. by a crash reporter,
+    /// after printing the banner. Enabled by default; the standard hook is never called, since it would report the panic twice.
+    /// A hook installed after the logger that calls the previous one works either way.
+    pub fn chain_panic_hook(mut self, enabled: bool) -> Self {
+        self.chain_panic_hook = enabled;
+        self
+    }
+
+    /// Whether the panic hook also writes the panic as an Error record to every sink,
+    /// so it ends up in log files and structured output. Enabled by default.
+    /// Text sinks on stdout and stderr skip the record, since the panic banner already shows it there.
+    pub fn log_panics(mut self, enabled: bool) -> Self {
+        self.log_panics = enabled;
+        self
+    }
+
+    /// Read the filter directives from this environment variable instead of `BIO_LOG`.
+    pub fn env_var(mut self, name: &str) -> Self {
+        self.env_var = Some(name.to_string());
+        self
+    }
+
+    /// Load levels, modules and sinks from this TOML file and reload them whenever it changes,
+    /// which is checked every two seconds. `BIO_LOG_CONFIG` (after the [`env_var`](Builder::env_var)) takes precedence.
+    ///
+    /// ```toml
+    /// level = "info"
+    ///
+    /// [modules]
+    /// mycrate = true                   # whitelisted at the global level
+    /// "mycrate::deserialize" = "trace"
+    /// rocket = "off"
+    ///
+    /// [[sinks]]
+    /// output = "stdout"                # or "stderr", "split" (with `split_level`) or "file" (with `path`)
+    /// level = "info"
+    ///
+    /// [[sinks]]
+    /// output = "file"
+    /// path = "app.log"
+    /// format = "json"                  # or "text" (with an optional `template`) or "logfmt"
+    /// max_size = 10485760
+    /// keep = 5
+    /// ```
+    /// Sinks also take `color`, `timestamp` (`local`, `utc`, `rfc3339`, `elapsed`, `delta` or `none`),
+    /// `timestamp_format`, `daily` and `compress`.
+    /// Settings removed from the file fall back to what was configured in code, except for sinks, which stay as they were.
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Self {
+        self.config_file = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Don't read filter directives from any environment variable.
+    pub fn no_env_var(mut self) -> Self {
+        self.env_var = None;
+        self
+    }
+
+    /// Choose what happens when messages are logged faster than they can be written.
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Set how many messages may wait for the logging thread before the [`Overflow`] policy kicks in.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    /// Create the logger without installing it, e.g. to wrap it in another logger.
+    /// The panic hook is still installed if enabled.
+    pub fn build(self) -> BioLogger {
+        let panic_hook: Option<PanicSettings> = self.panic_settings();
+        let (logger, startup): (BioLogger, Startup) = self.build_logger();
+        startup.run(&logger.shared);
+        if let Some(settings) = panic_hook {
+            panic::install(settings, Arc::downgrade(&logger.shared));
+        }
+        logger
+    }
+
+    /// Install the logger, panicking if another logger has already been set.
+    pub fn init(self) -> LoggerGuard {
+        self.try_init().expect("Failed to set boxed logger")
+    }
+
+    /// Install the logger, failing if another logger has already been set.
+    pub fn try_init(self) -> Result<LoggerGuard, Error> {
+        let panic_hook: Option<PanicSettings> = self.panic_settings();
+        let (logger, startup): (BioLogger, Startup) = self.build_logger();
+        let shared: Arc<Shared> = logger.shared.clone();
+
+        log::set_boxed_logger(Box::new(logger)).map_err(Error::AlreadySet)?;
+        shared.installed.store(true, Ordering::Relaxed);
+        // through an empty update, so a change made by another thread at the same time can't be overwritten
+        shared.update(|_| {});
+        startup.run(&shared);
+        // only touch the global panic hook once we're sure to be the active logger
+        if let Some(settings) = panic_hook {
+            panic::install(settings, Arc::downgrade(&shared));
+        }
+        Ok(LoggerGuard { shared })
+    }
+
+    fn panic_settings(&self) -> Option<PanicSettings> {
+        self.panic_hook.then(|| PanicSettings {
+            color: self.color.enabled_for(Stream::Stderr),
+            chain: self.chain_panic_hook,
+            log: self.log_panics,
+        })
+    }
+
+    fn build_logger(self) -> (BioLogger, Startup) {
+        // directives from the environment come last so they override the ones set in code
+        let mut errors: Vec<String> = self.errors;
+        let mut directives: Vec<(String, Option<LevelFilter>)> = self.directives;
+        let mut level: Option<LevelFilter> = self.level;
+        let mut include: Vec<Regex> = self.include;
+        let mut exclude: Vec<Regex> = self.exclude;
+        if let Some(name) = &self.env_var && let Ok(spec) = std::env::var(name) {
+            let env_directives = Directives::parse(&spec);
+            level = env_directives.level.or(level);
+            directives.extend(env_directives.modules);
+            include.extend(env_directives.include);
+            exclude.extend(env_directives.exclude);
+            errors.extend(env_directives.errors.into_iter().map(|error| format!("Ignoring malformed {name} directive {error}")));
+        }
+        let level: LevelFilter = level.unwrap_or(if cfg!(debug_assertions) {
+            LevelFilter::Trace
+        } else {
+            LevelFilter::Info
+        });
+
+        timestamp::mark_start();
+
+        let mut format: Format = self.format.unwrap_or_else(|| Format::default_text(self.show_thread));
+        if let Some(name) = &self.env_var && let Ok(value) = std::env::var(format!("{name}_FORMAT")) {
+            match Format::from_name(&value, self.show_thread) {
+                Some(env_format) => format = env_format,
+                None => errors.push(format!("Ignoring unknown {name}_FORMAT `{value}`, expected `text`, `json` or `logfmt`")),
+            }
+        }
+
+        let mut sinks: Vec<Sink> = self.sinks;
+        if sinks.is_empty() {
+            sinks.push(Sink::new(self.output));
+        }
+        for sink in &mut sinks {
+            sink.resolve(self.color, &self.timestamp, &format);
+        }
+        let defaults = SinkDefaults {
+            color: self.color,
+            timestamp: self.timestamp,
+            format,
+        };
+        let sinks = Sinks(sinks);
+        let sink_level: LevelFilter = sinks.max_level();
+
+        let mut filter = Filter::new(level);
+        filter.set_match_mode(self.match_mode);
+        for (module, level) in &directives {
+            if let Err(error) = filter.insert(module, *level) {
+                errors.push(format!("Ignoring malformed filter directive `{module}`: {error}"));
+            }
+        }
+        include.into_iter().for_each(|regex| filter.include_messages(regex));
+        exclude.into_iter().for_each(|regex| filter.exclude_messages(regex));
+
+        let worker: Arc<LogWorker> = Arc::new(LogWorker::spawn(self.queue_capacity, sinks));
+        let config = Config {
+            filter,
+            sink_level,
+            overflow: self.overflow,
+        };
+        let shared: Arc<Shared> = Arc::new(Shared::new(config, worker, defaults));
+
+        let mut config_file: Option<PathBuf> = self.config_file;
+        if let Some(name) = &self.env_var && let Some(path) = std::env::var_os(format!("{name}_CONFIG")) {
+            config_file = Some(PathBuf::from(path));
+        }
+
+        (BioLogger { shared }, Startup { errors, config_file })
+    }
+}
+
+/// What a new logger does once it is certain to be used, so a failed [`Builder::try_init`]
+/// neither prints warnings nor starts watching a config file.
+struct Startup {
+    errors: Vec<String>,
+    config_file: Option<PathBuf>,
+}
+
+impl Startup {
+    fn run(self, shared: &Arc<Shared>) {
+        // report malformed directives regardless of the filter, they are probably why logs are missing
+        let overflow: Overflow = shared.config.load().overflow;
+        for error in self.errors {
+            shared.worker.write(Entry::internal(Level::Warn, error), overflow);
+        }
+        if let Some(path) = self.config_file {
+            config::watch(path, shared);
+        }
+    }
+}
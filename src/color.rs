@@ -0,0 +1,71 @@
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use colored::Color;
+
+/// Whether log lines are colored with ANSI escape codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color streams that are terminals, honoring `NO_COLOR`, `CLICOLOR_FORCE` and `CLICOLOR`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Which standard stream an `Auto` choice is resolved for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stream {
+    Stdout,
+    Stderr,
+    /// Files and custom writers are never terminals.
+    Other,
+}
+
+impl ColorChoice {
+    pub(crate) fn enabled_for(self, stream: Stream) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => env_override().unwrap_or_else(|| match stream {
+                Stream::Stdout => std::io::stdout().is_terminal(),
+                Stream::Stderr => std::io::stderr().is_terminal(),
+                Stream::Other => false,
+            }),
+        }
+    }
+}
+
+/// See <https://no-color.org> and <https://bixense.com/clicolors>.
+fn env_override() -> Option<bool> {
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return Some(false);
+    }
+    if std::env::var_os("CLICOLOR_FORCE").is_some_and(|value| !value.is_empty() && value != "0") {
+        return Some(true);
+    }
+    if std::env::var_os("CLICOLOR").is_some_and(|value| value == "0") {
+        return Some(false);
+    }
+    None
+}
+
+/// Applies colors only if enabled. Unlike `colored::Colorize` this ignores `colored`'s global
+/// decision, which only looks at stdout and would override an explicit [`ColorChoice`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Painter(pub(crate) bool);
+
+impl Painter {
+    pub(crate) fn paint<'a>(self, text: &'a str, color: Color) -> Cow<'a, str> {
+        if !self.0 || text.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(format!("\x1b[{}m{text}\x1b[0m", color.to_fg_str()))
+    }
+
+    pub(crate) fn dim(self, text: &str) -> Cow<'_, str> {
+        if !self.0 || text.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(format!("\x1b[2m{text}\x1b[0m"))
+    }
+}
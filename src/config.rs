@@ -0,0 +1,501 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use log::{Level, LevelFilter};
+use toml::{Table, Value};
+use crate::color::ColorChoice;
+use crate::file::FileOutput;
+use crate::filter::{parse_level, Filter};
+use crate::format::{Format, Template};
+use crate::handle::Shared;
+use crate::output::{Output, Sink, Sinks};
+use crate::timestamp::{Timestamp, DEFAULT_TIMESTAMP_FORMAT};
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The settings read from the config file, before they are applied.
+#[derive(Default)]
+struct FileConfig {
+    level: Option<LevelFilter>,
+    modules: Vec<(String, Option<LevelFilter>)>,
+    /// Kept unparsed so unchanged sinks don't reopen their files on every reload.
+    sinks: Option<Value>,
+}
+
+/// Applies a TOML config file to a logger, and again whenever the file changes.
+/// Settings removed from the file fall back to what the builder configured, except for sinks, which stay as they were.
+struct ConfigFile {
+    path: PathBuf,
+    /// The filter as built, to restore entries that were removed from the file.
+    base: Filter,
+    applied: FileConfig,
+    /// Modification time and size of the file when it was last checked, `None` if it was missing.
+    stamp: Option<(SystemTime, u64)>,
+    checked: bool,
+}
+
+/// Apply the file right away, then keep polling it on a background thread until the logger shuts down.
+pub(crate) fn watch(path: PathBuf, shared: &Arc<Shared>) {
+    let mut config = ConfigFile {
+        path,
+        base: shared.config.load().filter.clone(),
+        applied: FileConfig::default(),
+        stamp: None,
+        checked: false,
+    };
+    config.poll(shared);
+
+    let weak: Weak<Shared> = Arc::downgrade(shared);
+    let spawned = thread::Builder::new()
+        .name("biologischer-log-config".to_string())
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let Some(shared) = weak.upgrade() else {
+                return;
+            };
+            // the installed logger is never dropped, but it can still be shut down
+            if shared.worker.is_closed() {
+                return;
+            }
+            config.poll(&shared);
+        });
+    if let Err(error) = spawned {
+        shared.warn(format!("Could not spawn config watcher thread, changes to the log config are ignored: {error}"));
+    }
+}
+
+impl ConfigFile {
+    fn poll(&mut self, shared: &Arc<Shared>) {
+        let stamp: Option<(SystemTime, u64)> = fs::metadata(&self.path).ok()
+            .map(|metadata| (metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH), metadata.len()));
+        // a missing file is reported once, not on every poll
+        if self.checked && stamp == self.stamp {
+            return;
+        }
+        self.stamp = stamp;
+        self.checked = true;
+        self.reload(shared);
+    }
+
+    fn reload(&mut self, shared: &Arc<Shared>) {
+        let path: String = self.path.display().to_string();
+        let text: String = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(error) => return shared.warn(format!("Could not read log config {path}: {error}")),
+        };
+        let table: Table = match text.parse() {
+            Ok(table) => table,
+            Err(error) => return shared.warn(format!("Could not parse log config {path}: {}", toml_error(&text, &error))),
+        };
+
+        let mut errors: Vec<String> = Vec::new();
+        let file: FileConfig = parse_config(table, &mut errors);
+
+        let mut sinks: Option<Sinks> = None;
+        if let Some(file_sinks) = &file.sinks && file.sinks != self.applied.sinks {
+            let parsed: Vec<Sink> = parse_sinks(file_sinks, &mut errors);
+            // don't lose all output because of a typo
+            if !parsed.is_empty() {
+                sinks = Some(shared.resolve_sinks(parsed));
+            }
+        }
+        let sink_level: Option<LevelFilter> = sinks.as_ref().map(Sinks::max_level);
+        if let Some(sinks) = sinks {
+            shared.worker.replace_sinks(sinks);
+        }
+
+        // all at once, so no record sees a half-applied file
+        let (base, applied): (&Filter, &FileConfig) = (&self.base, &self.applied);
+        shared.update(|config| {
+            if file.level != applied.level {
+                config.filter.set_level(file.level.unwrap_or(base.level()));
+            }
+            for (module, _) in &applied.modules {
+                if file.modules.iter().any(|(file_module, _)| file_module == module) {
+                    continue;
+                }
+                match base.get(module) {
+                    // compiled fine when the logger was built
+                    Some(level) => {
+                        config.filter.insert(module, level).ok();
+                    }
+                    None => config.filter.remove(module),
+                }
+            }
+            for (module, level) in &file.modules {
+                if let Err(error) = config.filter.insert(module, *level) {
+                    errors.push(format!("`modules.{module}`: {error}"));
+                }
+            }
+            if let Some(sink_level) = sink_level {
+                config.sink_level = sink_level;
+            }
+        });
+        self.applied = file;
+
+        for error in errors {
+            shared.warn(format!("Ignoring malformed entry in log config {path}: {error}"));
+        }
+    }
+}
+
+/// `line N: message` on a single line, since the full error draws the line with a caret underneath.
+fn toml_error(text: &str, error: &toml::de::Error) -> String {
+    let message: String = error.message().trim().replace('\n', ", ");
+    match error.span() {
+        Some(span) => {
+            let line: usize = text[..span.start.min(text.len())].matches('\n').count() + 1;
+            format!("line {line}: {message}")
+        }
+        None => message,
+    }
+}
+
+fn parse_config(table: Table, errors: &mut Vec<String>) -> FileConfig {
+    let mut config = FileConfig::default();
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            ("level", Value::String(level)) => match parse_level(&level) {
+                Some(level) => config.level = Some(level),
+                None => errors.push(format!("`level`: unknown level `{level}`")),
+            },
+            ("modules", Value::Table(modules)) => {
+                for (module, value) in modules {
+                    // `"!rocket::server" = true` is another way to write `"rocket::server" = "off"`
+                    if let Some(denied) = module.strip_prefix('!') {
+                        match value {
+                            Value::Boolean(true) => config.modules.push((denied.to_string(), Some(LevelFilter::Off))),
+                            _ => errors.push(format!("`modules.{module}`: a denied module can only be `true`")),
+                        }
+                        continue;
+                    }
+                    match value {
+                        Value::Boolean(true) => config.modules.push((module, None)),
+                        Value::Boolean(false) => config.modules.push((module, Some(LevelFilter::Off))),
+                        Value::String(level) => match parse_level(&level) {
+                            Some(level) => config.modules.push((module, Some(level))),
+                            None => errors.push(format!("`modules.{module}`: unknown level `{level}`")),
+                        },
+                        _ => errors.push(format!("`modules.{module}`: expected a level or a boolean")),
+                    }
+                }
+            }
+            ("sinks", sinks @ Value::Array(_)) => config.sinks = Some(sinks),
+            ("level" | "modules" | "sinks", _) => errors.push(format!("`{key}`: unexpected type")),
+            _ => errors.push(format!("`{key}`: unknown setting")),
+        }
+    }
+    config
+}
+
+fn parse_sinks(sinks: &Value, errors: &mut Vec<String>) -> Vec<Sink> {
+    let Value::Array(sinks) = sinks else {
+        return Vec::new();
+    };
+    let mut parsed: Vec<Sink> = Vec::new();
+    for (index, sink) in sinks.iter().enumerate() {
+        let name: String = format!("sinks[{index}]");
+        match sink {
+            Value::Table(table) => match parse_sink(table, &name, errors) {
+                Ok(sink) => parsed.push(sink),
+                Err(error) => errors.push(format!("`{name}`: {error}")),
+            },
+            _ => errors.push(format!("`{name}`: expected a table")),
+        }
+    }
+    parsed
+}
+
+/// Settings that are wrong are skipped with an error; an output that can't be opened skips the whole sink.
+fn parse_sink(table: &Table, name: &str, errors: &mut Vec<String>) -> Result<Sink, String> {
+    let mut sink = Sink::new(parse_output(table)?);
+    let mut timestamp: Option<&str> = None;
+    let mut timestamp_format: Option<&str> = None;
+
+    for (key, value) in table {
+        if matches!(key.as_str(), "output" | "path" | "split_level" | "max_size" | "daily" | "keep" | "compress") {
+            continue;
+        }
+        let Value::String(value) = value else {
+            errors.push(format!("`{name}.{key}`: expected a string"));
+            continue;
+        };
+        match key.as_str() {
+            "level" => match parse_level(value) {
+                Some(level) => sink = sink.level(level),
+                None => errors.push(format!("`{name}.level`: unknown level `{value}`")),
+            },
+            "color" => match value.to_lowercase().as_str() {
+                "auto" => sink = sink.color(ColorChoice::Auto),
+                "always" => sink = sink.color(ColorChoice::Always),
+                "never" => sink = sink.color(ColorChoice::Never),
+                _ => errors.push(format!("`{name}.color`: unknown color choice `{value}`, expected `auto`, `always` or `never`")),
+            },
+            "format" => match Format::from_name(value, false) {
+                Some(format) => sink = sink.format(format),
+                None => errors.push(format!("`{name}.format`: unknown format `{value}`, expected `text`, `json` or `logfmt`")),
+            },
+            "template" => match Template::parse(value) {
+                Ok(template) => sink = sink.template(template),
+                Err(error) => errors.push(format!("`{name}.template`: {error}")),
+            },
+            "timestamp" => timestamp = Some(value),
+            "timestamp_format" => timestamp_format = Some(value),
+            _ => errors.push(format!("`{name}.{key}`: unknown setting")),
+        }
+    }
+
+    if timestamp.is_some() || timestamp_format.is_some() {
+        let pattern: String = timestamp_format.unwrap_or(DEFAULT_TIMESTAMP_FORMAT).to_string();
+        match timestamp.unwrap_or("local").to_lowercase().as_str() {
+            "local" => sink = sink.timestamp(Timestamp::Local(pattern)),
+            "utc" => sink = sink.timestamp(Timestamp::Utc(pattern)),
+            "rfc3339" => sink = sink.timestamp(Timestamp::Rfc3339),
+            "elapsed" => sink = sink.timestamp(Timestamp::Elapsed),
+            "delta" => sink = sink.timestamp(Timestamp::Delta),
+            "none" => sink = sink.timestamp(Timestamp::None),
+            mode => errors.push(format!("`{name}.timestamp`: unknown mode `{mode}`")),
+        }
+    }
+    Ok(sink)
+}
+
+fn parse_output(table: &Table) -> Result<Output, String> {
+    let output: &str = match table.get("output") {
+        Some(Value::String(output)) => output,
+        Some(_) => return Err("`output` must be a string".to_string()),
+        None => "stdout",
+    };
+    match output.to_lowercase().as_str() {
+        "stdout" => Ok(Output::Stdout),
+        "stderr" => Ok(Output::Stderr),
+        "split" => match table.get("split_level") {
+            None => Ok(Output::Split(Level::Warn)),
+            Some(Value::String(level)) => level.parse::<Level>()
+                .map(Output::Split)
+                .map_err(|_| format!("unknown split level `{level}`")),
+            Some(_) => Err("`split_level` must be a string".to_string()),
+        },
+        "file" => {
+            let Some(Value::String(path)) = table.get("path") else {
+                return Err("a file output needs a `path`".to_string());
+            };
+            parse_file(table, Path::new(path)).map(Output::File)
+        }
+        _ => Err(format!("unknown output `{output}`, expected `stdout`, `stderr`, `split` or `file`")),
+    }
+}
+
+fn parse_file(table: &Table, path: &Path) -> Result<FileOutput, String> {
+    let mut file: FileOutput = FileOutput::open(path)
+        .map_err(|error| format!("could not open {}: {error}", path.display()))?;
+    match table.get("max_size") {
+        Some(Value::Integer(bytes)) if *bytes > 0 => file = file.max_size(*bytes as u64),
+        Some(_) => return Err("`max_size` must be a positive number of bytes".to_string()),
+        None => {}
+    }
+    match table.get("keep") {
+        Some(Value::Integer(count)) if *count >= 0 => file = file.keep(*count as usize),
+        Some(_) => return Err("`keep` must be a number".to_string()),
+        None => {}
+    }
+    match table.get("daily") {
+        Some(Value::Boolean(true)) => file = file.daily(),
+        Some(Value::Boolean(false)) | None => {}
+        Some(_) => return Err("`daily` must be a boolean".to_string()),
+    }
+    match table.get("compress") {
+        Some(Value::Boolean(compress)) => file = file.compress(*compress),
+        Some(_) => return Err("`compress` must be a boolean".to_string()),
+        None => {}
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle::{Config, SinkDefaults};
+    use crate::worker::{LogWorker, Overflow};
+
+    fn table(text: &str) -> Table {
+        text.parse().unwrap()
+    }
+
+    fn config(text: &str) -> (FileConfig, Vec<String>) {
+        let mut errors: Vec<String> = Vec::new();
+        let config: FileConfig = parse_config(table(text), &mut errors);
+        (config, errors)
+    }
+
+    fn sink(text: &str) -> (Result<Sink, String>, Vec<String>) {
+        let mut errors: Vec<String> = Vec::new();
+        let sink: Result<Sink, String> = parse_sink(&table(text), "sinks[0]", &mut errors);
+        (sink, errors)
+    }
+
+    fn output_error(text: &str) -> String {
+        parse_output(&table(text)).err().expect("output should be rejected")
+    }
+
+    #[test]
+    fn parse_config_settings() {
+        let (config, errors) = config(r#"
+            level = "debug"
+            sinks = [{ output = "stderr" }]
+
+            [modules]
+            mycrate = true
+            "mycrate::noisy" = false
+            rocket = "warn"
+            "!rocket::server" = true
+        "#);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(config.level, Some(LevelFilter::Debug));
+        // denied modules sort first, since the table is ordered by key
+        assert_eq!(config.modules, [
+            ("rocket::server".to_string(), Some(LevelFilter::Off)),
+            ("mycrate".to_string(), None),
+            ("mycrate::noisy".to_string(), Some(LevelFilter::Off)),
+            ("rocket".to_string(), Some(LevelFilter::Warn)),
+        ]);
+        assert!(config.sinks.is_some());
+    }
+
+    #[test]
+    fn parse_config_errors() {
+        let (config, errors) = config(r#"
+            level = "loud"
+            sinks = "stdout"
+            colour = "never"
+
+            [modules]
+            mycrate = 3
+            rocket = "noisy"
+            "!hyper" = false
+        "#);
+        assert_eq!(config.level, None);
+        assert!(config.modules.is_empty());
+        assert!(config.sinks.is_none());
+        assert_eq!(errors, [
+            "`colour`: unknown setting",
+            "`level`: unknown level `loud`",
+            "`modules.!hyper`: a denied module can only be `true`",
+            "`modules.mycrate`: expected a level or a boolean",
+            "`modules.rocket`: unknown level `noisy`",
+            "`sinks`: unexpected type",
+        ]);
+    }
+
+    #[test]
+    fn parse_sink_settings() {
+        let (parsed, errors) = sink(r#"
+            output = "stderr"
+            level = "warn"
+            color = "never"
+            format = "json"
+            timestamp = "utc"
+            timestamp_format = "%H:%M"
+        "#);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(parsed.unwrap().max_level(), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn parse_sink_errors() {
+        let (parsed, errors) = sink(r#"
+            level = 3
+            color = "sometimes"
+            format = "xml"
+            template = "{lvl}"
+            timestamp = "tomorrow"
+            colour = "never"
+        "#);
+        // the sink is still used, without the broken settings
+        assert_eq!(parsed.unwrap().max_level(), LevelFilter::Trace);
+        assert_eq!(errors, [
+            "`sinks[0].color`: unknown color choice `sometimes`, expected `auto`, `always` or `never`",
+            "`sinks[0].colour`: unknown setting",
+            "`sinks[0].format`: unknown format `xml`, expected `text`, `json` or `logfmt`",
+            "`sinks[0].level`: expected a string",
+            "`sinks[0].template`: Invalid log template: unknown field `lvl`",
+            "`sinks[0].timestamp`: unknown mode `tomorrow`",
+        ]);
+
+        let (parsed, errors) = sink(r#"output = "printer""#);
+        assert!(parsed.is_err());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn parse_outputs() {
+        assert!(matches!(parse_output(&Table::new()), Ok(Output::Stdout)));
+        assert!(matches!(parse_output(&table(r#"output = "STDERR""#)), Ok(Output::Stderr)));
+        assert!(matches!(parse_output(&table(r#"output = "split""#)), Ok(Output::Split(Level::Warn))));
+        assert!(matches!(parse_output(&table(r#"output = "split"
+            split_level = "error""#)), Ok(Output::Split(Level::Error))));
+
+        assert_eq!(output_error("output = 1"), "`output` must be a string");
+        assert_eq!(output_error(r#"output = "printer""#), "unknown output `printer`, expected `stdout`, `stderr`, `split` or `file`");
+        assert_eq!(output_error(r#"output = "split"
+            split_level = "loud""#), "unknown split level `loud`");
+        assert_eq!(output_error(r#"output = "file""#), "a file output needs a `path`");
+    }
+
+    #[test]
+    fn parse_file_outputs() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("biologischer-log-config-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path: String = dir.join("app.log").display().to_string().replace('\\', "/");
+        let file = |settings: &str| parse_output(&table(&format!("output = \"file\"\npath = \"{path}\"\n{settings}")));
+
+        assert!(matches!(file("max_size = 1024\nkeep = 0\ndaily = true\ncompress = true"), Ok(Output::File(_))));
+        assert_eq!(file("max_size = 0").err().unwrap(), "`max_size` must be a positive number of bytes");
+        assert_eq!(file("keep = -1").err().unwrap(), "`keep` must be a number");
+        assert_eq!(file(r#"daily = "yes""#).err().unwrap(), "`daily` must be a boolean");
+        assert_eq!(file("compress = 1").err().unwrap(), "`compress` must be a boolean");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reload_restores_builder_entries() {
+        let dir: PathBuf = std::env::temp_dir().join(format!("biologischer-log-config-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join("log.toml");
+
+        let mut filter = Filter::new(LevelFilter::Info);
+        filter.insert("mycrate", None).unwrap();
+        filter.insert("rocket", Some(LevelFilter::Warn)).unwrap();
+        let shared: Arc<Shared> = Arc::new(Shared::new(
+            Config { filter: filter.clone(), sink_level: LevelFilter::Trace, overflow: Overflow::Block },
+            Arc::new(LogWorker::spawn(16, Sinks(Vec::new()))),
+            SinkDefaults { color: ColorChoice::Never, timestamp: Timestamp::None, format: Format::default() },
+        ));
+        let mut config = ConfigFile { path: path.clone(), base: filter, applied: FileConfig::default(), stamp: None, checked: false };
+
+        fs::write(&path, "level = \"debug\"\n[modules]\nrocket = \"trace\"\nhyper = true\n").unwrap();
+        config.reload(&shared);
+        let filter: Filter = shared.config.load().filter.clone();
+        assert_eq!(filter.level(), LevelFilter::Debug);
+        assert_eq!(filter.get("rocket"), Some(Some(LevelFilter::Trace)));
+        assert_eq!(filter.get("hyper"), Some(None));
+
+        fs::write(&path, "[modules]\nmycrate = \"off\"\n").unwrap();
+        config.reload(&shared);
+        let filter: Filter = shared.config.load().filter.clone();
+        assert_eq!(filter.level(), LevelFilter::Info);
+        assert_eq!(filter.get("mycrate"), Some(Some(LevelFilter::Off)));
+        assert_eq!(filter.get("rocket"), Some(Some(LevelFilter::Warn)));
+        assert_eq!(filter.get("hyper"), None);
+
+        fs::write(&path, "").unwrap();
+        config.reload(&shared);
+        assert_eq!(shared.config.load().filter.get("mycrate"), Some(None));
+
+        shared.worker.shutdown();
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,434 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use chrono::NaiveDate;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+/// How long to wait before retrying a file that could not be opened or rotated; doubled after every failure.
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Bumped by [`reopen_files`]; every [`FileOutput`] reopens its file once it sees a new value.
+static REOPEN_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Ask every log file to be closed and reopened before its next write,
+/// e.g. after logrotate moved it away. Only touches an atomic, so it is safe to call from a signal handler.
+pub fn reopen_files() {
+    REOPEN_GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Appends plain log lines without colors to a file, optionally rotating it.
+///
+/// Rotated files are renamed to `<path>.1`, `<path>.2`, ... with `.1` being the newest.
+/// Compressing a rotated file happens on a thread of its own, so logging doesn't wait for it.
+/// If the file can't be written, e.g. because its directory was removed, this is reported on stderr once
+/// and reopening it is retried after one second, then less and less often; lines logged in between are lost.
+///
+/// Example use:
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use biologischer_log::{FileOutput, Output};
+///
+/// let output = Output::File(FileOutput::open("app.log")?.max_size(10 * 1024 * 1024).daily().keep(7).compress(true));
+/// # Ok(())
+/// # }
+/// ```
+pub struct FileOutput {
+    path: PathBuf,
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+    compress: bool,
+    /// `None` after opening or writing to the file failed; opening is retried once `open_retry` allows it.
+    file: Option<BufWriter<File>>,
+    size: u64,
+    day: NaiveDate,
+    generation: u64,
+    open_retry: Retry,
+    rotate_retry: Retry,
+    /// Gzips the file rotated last; finished before the next rotation touches the rotated files.
+    compressing: Option<JoinHandle<()>>,
+}
+
+/// Reports a failing operation once, until it works again, and spaces out the attempts to retry it
+/// so a full disk or a missing directory doesn't cost a syscall and a line on stderr per record.
+struct Retry {
+    /// Set once the failure has been reported.
+    failing: bool,
+    next: Option<Instant>,
+    delay: Duration,
+}
+
+impl Retry {
+    fn new() -> Self {
+        Retry {
+            failing: false,
+            next: None,
+            delay: MIN_RETRY_DELAY,
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.next.is_none_or(|next| Instant::now() >= next)
+    }
+
+    fn failed(&mut self, path: &Path, action: &str, error: &io::Error) {
+        if !self.failing {
+            self.failing = true;
+            report_error(path, action, error);
+        }
+        self.next = Some(Instant::now() + self.delay);
+        self.delay = (self.delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    fn succeeded(&mut self) {
+        *self = Retry::new();
+    }
+}
+
+impl FileOutput {
+    /// Open (or create) the file for appending. Nothing is rotated until configured.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut output = FileOutput {
+            path: path.as_ref().to_path_buf(),
+            max_size: None,
+            daily: false,
+            keep: 5,
+            compress: false,
+            file: None,
+            size: 0,
+            day: today(),
+            generation: REOPEN_GENERATION.load(Ordering::Relaxed),
+            open_retry: Retry::new(),
+            rotate_retry: Retry::new(),
+            compressing: None,
+        };
+        output.open_file()?;
+        Ok(output)
+    }
+
+    /// Rotate the file before it grows beyond this many bytes.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate the file when the local date changes.
+    pub fn daily(mut self) -> Self {
+        self.daily = true;
+        self
+    }
+
+    /// How many rotated files to keep; older ones are deleted. Defaults to 5.
+    pub fn keep(mut self, count: usize) -> Self {
+        self.keep = count;
+        self
+    }
+
+    /// Gzip rotated files, adding a `.gz` suffix.
+    pub fn compress(mut self, enabled: bool) -> Self {
+        self.compress = enabled;
+        self
+    }
+
+    fn open_file(&mut self) -> io::Result<()> {
+        let file: File = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let metadata: fs::Metadata = file.metadata()?;
+        self.size = metadata.len();
+        // an existing file counts as written on the day it was last modified
+        self.day = metadata.modified()
+            .map(|time| chrono::DateTime::<chrono::Local>::from(time).date_naive())
+            .unwrap_or_else(|_| today());
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    pub(crate) fn write_line(&mut self, line: &str) {
+        let generation: u64 = REOPEN_GENERATION.load(Ordering::Relaxed);
+        if generation != self.generation {
+            self.generation = generation;
+            self.close();
+            // whoever asked to reopen probably fixed whatever was wrong
+            self.open_retry.next = None;
+        }
+
+        let bytes: u64 = line.len() as u64 + 1;
+        let too_big: bool = self.max_size.is_some_and(|max_size| self.size > 0 && self.size + bytes > max_size);
+        let new_day: bool = self.daily && self.day != today();
+        if self.file.is_some() && (too_big || new_day) && self.rotate_retry.ready() {
+            self.rotate();
+        }
+
+        // lines are dropped while the file can't be opened
+        if self.file.is_none() {
+            if !self.open_retry.ready() {
+                return;
+            }
+            if let Err(error) = self.open_file() {
+                self.open_retry.failed(&self.path, "open", &error);
+                return;
+            }
+        }
+        if let Some(file) = &mut self.file {
+            match writeln!(file, "{line}") {
+                Ok(()) => self.size += bytes,
+                Err(error) => {
+                    self.open_retry.failed(&self.path, "write to", &error);
+                    self.file = None;
+                }
+            }
+        }
+    }
+
+    /// Only a flush reaches the disk, so only a successful one counts as the file working again.
+    pub(crate) fn flush(&mut self) {
+        if let Some(file) = &mut self.file {
+            match file.flush() {
+                Ok(()) => self.open_retry.succeeded(),
+                Err(error) => {
+                    self.open_retry.failed(&self.path, "write to", &error);
+                    self.file = None;
+                }
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.flush();
+        self.file = None;
+    }
+
+    fn rotate(&mut self) {
+        self.close();
+        match self.shift_files() {
+            Ok(()) => self.rotate_retry.succeeded(),
+            // keep appending to the current file in the meantime
+            Err(error) => self.rotate_retry.failed(&self.path, "rotate", &error),
+        }
+    }
+
+    /// Rename `<path>.N` to `<path>.N+1`, dropping the oldest, then move the current file to `<path>.1`.
+    fn shift_files(&mut self) -> io::Result<()> {
+        self.wait_for_compression();
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        remove_if_exists(&self.rotated_path(self.keep, false))?;
+        remove_if_exists(&self.rotated_path(self.keep, true))?;
+        for index in (1..self.keep).rev() {
+            for compressed in [false, true] {
+                let from: PathBuf = self.rotated_path(index, compressed);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1, compressed))?;
+                }
+            }
+        }
+
+        let rotated: PathBuf = self.rotated_path(1, false);
+        fs::rename(&self.path, &rotated)?;
+        if self.compress {
+            let compressed: PathBuf = self.rotated_path(1, true);
+            let (thread_from, thread_to) = (rotated.clone(), compressed.clone());
+            let spawned = thread::Builder::new()
+                .name("biologischer-log-gzip".to_string())
+                .spawn(move || compress_rotated(&thread_from, &thread_to));
+            match spawned {
+                Ok(handle) => self.compressing = Some(handle),
+                Err(_) => compress_rotated(&rotated, &compressed),
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_for_compression(&mut self) {
+        if let Some(handle) = self.compressing.take() {
+            handle.join().ok();
+        }
+    }
+
+    fn rotated_path(&self, index: usize, compressed: bool) -> PathBuf {
+        let mut path: OsString = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        if compressed {
+            path.push(".gz");
+        }
+        PathBuf::from(path)
+    }
+}
+
+impl Drop for FileOutput {
+    /// The file rotated last should be complete once the logger is gone.
+    fn drop(&mut self) {
+        self.wait_for_compression();
+    }
+}
+
+fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// A rotated file that can't be compressed is kept as it is.
+fn compress_rotated(from: &Path, to: &Path) {
+    if let Err(error) = compress_file(from, to) {
+        report_error(from, "compress", &error);
+    }
+}
+
+fn compress_file(from: &Path, to: &Path) -> io::Result<()> {
+    let mut input: File = File::open(from)?;
+    let compressed: io::Result<()> = File::create(to).and_then(|output| {
+        let mut encoder = GzEncoder::new(output, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    });
+    if let Err(error) = compressed {
+        // a partial archive would pass for the whole file
+        remove_if_exists(to).ok();
+        return Err(error);
+    }
+    fs::remove_file(from)
+}
+
+/// The logger can't log its own failures, so they go straight to stderr.
+fn report_error(path: &Path, action: &str, error: &io::Error) {
+    eprintln!("biologischer-log: Could not {action} log file {}: {error}", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    /// An empty directory of its own for every test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir: PathBuf = std::env::temp_dir().join(format!("biologischer-log-{name}-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn read_gz(path: &Path) -> String {
+        let mut content = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut content).unwrap();
+        content
+    }
+
+    fn write_lines(output: &mut FileOutput, lines: &[&str]) {
+        for line in lines {
+            output.write_line(line);
+            output.flush();
+        }
+    }
+
+    #[test]
+    fn size_rotation() {
+        let dir: PathBuf = temp_dir("size");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap().max_size(15).keep(2);
+        write_lines(&mut output, &["line-001", "line-002", "line-003", "line-004"]);
+        assert_eq!(read(&path), "line-004\n");
+        assert_eq!(read(&dir.join("app.log.1")), "line-003\n");
+        assert_eq!(read(&dir.join("app.log.2")), "line-002\n");
+        assert!(!dir.join("app.log.3").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn lines_fitting_the_size_share_a_file() {
+        let dir: PathBuf = temp_dir("fitting");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap().max_size(18);
+        write_lines(&mut output, &["line-001", "line-002", "line-003"]);
+        assert_eq!(read(&path), "line-003\n");
+        assert_eq!(read(&dir.join("app.log.1")), "line-001\nline-002\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn keep_nothing() {
+        let dir: PathBuf = temp_dir("keep-nothing");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap().max_size(15).keep(0);
+        write_lines(&mut output, &["line-001", "line-002", "line-003"]);
+        assert_eq!(read(&path), "line-003\n");
+        assert!(!dir.join("app.log.1").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn daily_rotation() {
+        let dir: PathBuf = temp_dir("daily");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap().daily();
+        write_lines(&mut output, &["line-001", "line-002"]);
+        assert!(!dir.join("app.log.1").exists());
+        output.day = today().pred_opt().unwrap();
+        write_lines(&mut output, &["line-003"]);
+        assert_eq!(read(&path), "line-003\n");
+        assert_eq!(read(&dir.join("app.log.1")), "line-001\nline-002\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn compressed_rotation() {
+        let dir: PathBuf = temp_dir("compressed");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap().max_size(15).keep(2).compress(true);
+        write_lines(&mut output, &["line-001", "line-002", "line-003", "line-004"]);
+        drop(output);
+        assert_eq!(read(&path), "line-004\n");
+        assert_eq!(read_gz(&dir.join("app.log.1.gz")), "line-003\n");
+        assert_eq!(read_gz(&dir.join("app.log.2.gz")), "line-002\n");
+        assert!(!dir.join("app.log.1").exists());
+        assert!(!dir.join("app.log.2").exists());
+        assert!(!dir.join("app.log.3.gz").exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn failed_compression_keeps_the_original() {
+        let dir: PathBuf = temp_dir("failed-compression");
+        // reading a directory fails after the archive has been created
+        let from: PathBuf = dir.join("app.log.1");
+        fs::create_dir(&from).unwrap();
+        let to: PathBuf = dir.join("app.log.1.gz");
+        assert!(compress_file(&from, &to).is_err());
+        assert!(from.exists());
+        assert!(!to.exists());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reopen() {
+        let dir: PathBuf = temp_dir("reopen");
+        let path: PathBuf = dir.join("app.log");
+        let mut output: FileOutput = FileOutput::open(&path).unwrap();
+        write_lines(&mut output, &["line-001"]);
+        // what logrotate does
+        fs::rename(&path, dir.join("app.log.old")).unwrap();
+        reopen_files();
+        write_lines(&mut output, &["line-002"]);
+        assert_eq!(read(&path), "line-002\n");
+        assert_eq!(read(&dir.join("app.log.old")), "line-001\n");
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,688 @@
+use log::{LevelFilter, Metadata, Record};
+use regex::Regex;
+
+/// What the whitelist is matched against. The first of these that any entry matches decides,
+/// so an entry for the target always takes precedence over one for the module or the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchMode {
+    /// Only the record's target, which is its module path unless the log macro sets a custom `target:`.
+    #[default]
+    Target,
+    /// The target, then the module path, so records with a custom target inside a whitelisted module aren't lost.
+    Module,
+    /// Like [`MatchMode::Module`], then the source file against entries containing a `/` or starting with `file:`,
+    /// e.g. `src/audio` for every file in that directory, `src/**/parse_*.rs` or `file:build.rs`.
+    File,
+}
+
+/// Allows a module and all of its submodules up to a level.
+#[derive(Debug, Clone)]
+struct Directive {
+    module: String,
+    /// Set if `module` is a glob like `*::sounds` rather than a plain module path.
+    glob: Option<Regex>,
+    /// Whether `module` is a file path like `src/audio` (or `file:build.rs`), which only matches source files.
+    file: bool,
+    /// `None` follows the global level.
+    level: Option<LevelFilter>,
+    /// Decides between several matching directives, see [`specificity`].
+    specificity: (usize, usize, bool),
+}
+
+impl Directive {
+    fn matches(&self, target: &str) -> bool {
+        match &self.glob {
+            Some(glob) => !self.file && glob.is_match(target),
+            None => !self.file && is_in_module(target, &self.module),
+        }
+    }
+
+    fn matches_file(&self, file: &str) -> bool {
+        match &self.glob {
+            Some(glob) => self.file && glob.is_match(file),
+            None => self.file && is_in_directory(file, &self.module),
+        }
+    }
+}
+
+/// Decides which records get logged. Modules that aren't whitelisted by any directive are muted.
+#[derive(Debug, Clone)]
+pub(crate) struct Filter {
+    level: LevelFilter,
+    directives: Vec<Directive>,
+    mode: MatchMode,
+    /// If not empty, a message has to match at least one of these.
+    include: Vec<Regex>,
+    /// A message matching any of these is dropped.
+    exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub(crate) fn new(level: LevelFilter) -> Self {
+        Filter {
+            level,
+            directives: Vec::new(),
+            mode: MatchMode::default(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_match_mode(&mut self, mode: MatchMode) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn include_messages(&mut self, regex: Regex) {
+        self.include.push(regex);
+    }
+
+    pub(crate) fn exclude_messages(&mut self, regex: Regex) {
+        self.exclude.push(regex);
+    }
+
+    /// Check the formatted message text against the include and exclude patterns.
+    pub(crate) fn message_allowed(&self, message: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|regex| regex.is_match(message)))
+            && !self.exclude.iter().any(|regex| regex.is_match(message))
+    }
+
+    /// Add a directive, replacing any previous one for exactly this module.
+    /// A deny rule like `!rocket::server` mutes the module even if a parent is whitelisted.
+    /// Leaves the filter unchanged if the module is a glob that can't be compiled.
+    pub(crate) fn insert(&mut self, module: &str, level: Option<LevelFilter>) -> Result<(), String> {
+        let (module, level): (&str, Option<LevelFilter>) = match module.strip_prefix('!') {
+            Some(module) => (module, Some(LevelFilter::Off)),
+            None => (module, level),
+        };
+        let (path, file): (&str, bool) = match module.strip_prefix("file:") {
+            Some(path) => (path, true),
+            None => (module, module.contains('/')),
+        };
+        let glob: Option<Regex> = glob_regex(path, file)?;
+        self.remove(module);
+        self.directives.push(Directive {
+            module: path.to_string(),
+            glob,
+            file,
+            level,
+            specificity: specificity(path, file),
+        });
+        Ok(())
+    }
+
+    /// Remove the directive for exactly this module, with or without the `!` of a deny rule.
+    pub(crate) fn remove(&mut self, module: &str) {
+        let module: &str = directive_name(module);
+        self.directives.retain(|directive| directive.module != module);
+    }
+
+    /// The level of directives that don't have their own.
+    pub(crate) fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Set the level of directives that don't have their own.
+    pub(crate) fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+    }
+
+    /// The level of the directive for exactly this module, if there is one.
+    pub(crate) fn get(&self, module: &str) -> Option<Option<LevelFilter>> {
+        let module: &str = directive_name(module);
+        self.directives.iter()
+            .find(|directive| directive.module == module)
+            .map(|directive| directive.level)
+    }
+
+    /// The level allowed for a target, taken from the most specific matching directive.
+    pub(crate) fn level_for(&self, target: &str) -> LevelFilter {
+        self.matching_level(target, Directive::matches).unwrap_or(LevelFilter::Off)
+    }
+
+    /// The level of the most specific directive matching `path`, if any does.
+    fn matching_level(&self, path: &str, matches: fn(&Directive, &str) -> bool) -> Option<LevelFilter> {
+        self.directives.iter()
+            .filter(|directive| matches(directive, path))
+            .max_by_key(|directive| directive.specificity)
+            .map(|directive| directive.level.unwrap_or(self.level))
+    }
+
+    /// Only the target is known here, so unless it matches an entry this lets everything through
+    /// that some entry allows, and [`Filter::record_enabled`] decides once the whole record is known.
+    pub(crate) fn enabled(&self, metadata: &Metadata) -> bool {
+        let level: Option<LevelFilter> = self.matching_level(metadata.target(), Directive::matches);
+        match self.mode {
+            MatchMode::Target => metadata.level() <= level.unwrap_or(LevelFilter::Off),
+            MatchMode::Module | MatchMode::File => metadata.level() <= level.unwrap_or_else(|| self.max_level()),
+        }
+    }
+
+    pub(crate) fn record_enabled(&self, record: &Record) -> bool {
+        let mut level: Option<LevelFilter> = self.matching_level(record.target(), Directive::matches);
+        if self.mode != MatchMode::Target && level.is_none() && let Some(module_path) = record.module_path() {
+            level = self.matching_level(module_path, Directive::matches);
+        }
+        if self.mode == MatchMode::File && level.is_none() && let Some(file) = record.file() {
+            level = self.matching_level(file, Directive::matches_file);
+        }
+        record.level() <= level.unwrap_or(LevelFilter::Off)
+    }
+
+    /// The most verbose level any module may log at, for `log::set_max_level`.
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        self.directives.iter()
+            .map(|directive| directive.level.unwrap_or(self.level))
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+}
+
+/// The module or path a directive is stored under, without the `!` of a deny rule or the `file:` prefix.
+fn directive_name(module: &str) -> &str {
+    let module: &str = module.trim_start_matches('!');
+    module.strip_prefix("file:").unwrap_or(module)
+}
+
+/// Whether `path` is `module` itself or one of its submodules.
+pub(crate) fn is_in_module(path: &str, module: &str) -> bool {
+    path.starts_with(module) &&
+        (path.len() == module.len() || path.as_bytes()[module.len()] == b':')
+}
+
+/// Whether `file` is `directory` itself or inside it.
+fn is_in_directory(file: &str, directory: &str) -> bool {
+    let directory: &str = directory.trim_end_matches('/');
+    file.starts_with(directory) &&
+        (file.len() == directory.len() || file.as_bytes()[directory.len()] == b'/')
+}
+
+/// The most specific directive wins: the one with the most path segments without wildcards,
+/// so `!hyper::proto` overrides `{tokio,hyper}` and `mycrate` overrides `**`.
+/// Ties go to the one with more segments, then to a plain module over a glob, then to the one added last.
+/// Alternatives in braces make up a single wildcard segment, whatever separators they contain.
+fn specificity(module: &str, file: bool) -> (usize, usize, bool) {
+    let separator: &str = if file { "/" } else { "::" };
+    let segments: Vec<&str> = split_outside_braces(module.trim_end_matches('/'), separator);
+    let literal: usize = segments.iter().filter(|segment| !segment.contains(['*', '?', '{', '}'])).count();
+    (literal, segments.len(), !module.contains(['*', '?', '{']))
+}
+
+/// Translate a module glob into a regex matching the same modules and their submodules, or `None` for a plain module.
+/// `*` and `?` stay within one path segment, `**` spans any number of them (including none)
+/// and `{a,b}` matches either alternative. A `{` without a matching `}` is taken literally. File globs work the same with `/` between segments.
+/// Fails if the regex gets too big, e.g. because of deeply nested braces.
+fn glob_regex(glob: &str, file: bool) -> Result<Option<Regex>, String> {
+    if !glob.contains(['*', '?', '{']) {
+        return Ok(None);
+    }
+    let (segment, separator): (&str, &str) = match file {
+        true => ("[^/]", "/"),
+        false => ("[^:]", "::"),
+    };
+    let pattern: String = format!("^{}(?:{separator}|$)", glob_pattern(glob, segment, separator));
+    match Regex::new(&pattern) {
+        Ok(regex) => Ok(Some(regex)),
+        Err(error) => Err(format!("invalid glob: {}", regex_error(&error))),
+    }
+}
+
+/// `segment` is the character class of a single character within one path segment,
+/// `separator` what comes between segments.
+fn glob_pattern(glob: &str, segment: &str, separator: &str) -> String {
+    let mut pattern = String::new();
+    let mut rest: &str = glob;
+    while let Some(char) = rest.chars().next() {
+        rest = &rest[char.len_utf8()..];
+        match char {
+            // `a::**::b` also matches `a::b`
+            '*' if let Some(after) = rest.strip_prefix('*').and_then(|after| after.strip_prefix(separator)) => {
+                rest = after;
+                pattern.push_str(&format!("(?:.*{})?", regex::escape(separator)));
+            }
+            '*' if rest.starts_with('*') => {
+                rest = &rest[1..];
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str(&format!("{segment}*")),
+            '?' => pattern.push_str(segment),
+            '{' if let Some(end) = closing_brace(rest) => {
+                let alternatives: Vec<String> = split_outside_braces(&rest[..end], ",").into_iter()
+                    .map(|alternative| glob_pattern(alternative, segment, separator))
+                    .collect();
+                pattern.push_str(&format!("(?:{})", alternatives.join("|")));
+                rest = &rest[end + 1..];
+            }
+            _ => pattern.push_str(&regex::escape(&char.to_string())),
+        }
+    }
+    pattern
+}
+
+/// The index of the `}` closing a `{` that was just consumed, skipping nested braces.
+fn closing_brace(glob: &str) -> Option<usize> {
+    let mut depth: usize = 0;
+    for (index, char) in glob.char_indices() {
+        match char {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split at the separators that aren't inside braces, e.g. the alternatives inside `{...}`,
+/// the directives of `{tokio,hyper}=debug,mycrate` or the segments of `{a::b,c}::d`.
+fn split_outside_braces<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts: Vec<&str> = Vec::new();
+    let mut depth: usize = 0;
+    let mut start: usize = 0;
+    let mut index: usize = 0;
+    while let Some(char) = text[index..].chars().next() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 && text[index..].starts_with(separator) => {
+                parts.push(&text[start..index]);
+                index += separator.len();
+                start = index;
+                continue;
+            }
+            _ => {}
+        }
+        index += char.len_utf8();
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// The index of the `/` starting the message pattern of a directive string.
+/// Slashes in the path of a `file:` entry don't count, unless they come after its level.
+/// Neither do those of a piece that looks like a file entry without the prefix, e.g. `src/audio=trace`,
+/// which is then reported instead of whitelisting `src` and dropping every message not matching `audio=trace`.
+fn pattern_start(spec: &str) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut piece_start: usize = 0;
+    for (index, char) in spec.char_indices() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => piece_start = index + 1,
+            '/' => {
+                let before: &str = spec[piece_start..index].trim_start().trim_start_matches('!');
+                if before.starts_with("file:") {
+                    if before.contains('=') {
+                        return Some(index);
+                    }
+                    continue;
+                }
+                let piece: &str = split_outside_braces(&spec[piece_start..], ",")[0];
+                if !looks_like_file_entry(piece) {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether a piece is a path with a level, like `src/audio=trace`, rather than a module followed by a message pattern.
+fn looks_like_file_entry(piece: &str) -> bool {
+    let piece: &str = piece.trim();
+    !piece.contains(char::is_whitespace)
+        && piece.split_once('=').is_some_and(|(path, level)| path.contains('/') && parse_level(level).is_some())
+}
+
+/// The result of parsing a directive string like `info,mycrate::sounds=trace,!rocket::server/pattern`.
+#[derive(Debug, Default)]
+pub(crate) struct Directives {
+    /// Set by a bare level such as `info`.
+    pub(crate) level: Option<LevelFilter>,
+    pub(crate) modules: Vec<(String, Option<LevelFilter>)>,
+    /// Set by a message pattern after the first `/`, e.g. `info/audio data length`.
+    pub(crate) include: Option<Regex>,
+    /// Set by a message pattern starting with `!`, e.g. `info/!audio data length`.
+    pub(crate) exclude: Option<Regex>,
+    /// One message per malformed piece; the rest of the string is still used.
+    pub(crate) errors: Vec<String>,
+}
+
+impl Directives {
+    pub(crate) fn parse(spec: &str) -> Self {
+        let mut directives = Directives::default();
+
+        // everything after the first slash is a regex on the message text, which may contain commas
+        let (spec, pattern): (&str, Option<&str>) = match pattern_start(spec) {
+            Some(index) => (&spec[..index], Some(&spec[index + 1..])),
+            None => (spec, None),
+        };
+        if let Some(pattern) = pattern {
+            let (pattern, exclude): (&str, bool) = match pattern.strip_prefix('!') {
+                Some(pattern) => (pattern, true),
+                None => (pattern, false),
+            };
+            match Regex::new(pattern) {
+                Ok(regex) if exclude => directives.exclude = Some(regex),
+                Ok(regex) => directives.include = Some(regex),
+                Err(error) => directives.errors.push(format!("`/{pattern}`: invalid regex: {}", regex_error(&error))),
+            }
+        }
+
+        for piece in split_outside_braces(spec, ",").into_iter().map(str::trim).filter(|piece| !piece.is_empty()) {
+            let mut parts = piece.split('=');
+            let module: &str = parts.next().unwrap_or_default().trim();
+            let level: Option<&str> = parts.next().map(str::trim);
+
+            if parts.next().is_some() {
+                directives.errors.push(format!("`{piece}`: more than one `=`"));
+                continue;
+            }
+            if module.is_empty() {
+                directives.errors.push(format!("`{piece}`: missing module name"));
+                continue;
+            }
+            if module.contains(char::is_whitespace) {
+                directives.errors.push(format!("`{piece}`: whitespace in module name"));
+                continue;
+            }
+            if module.starts_with('!') && level.is_some() {
+                directives.errors.push(format!("`{piece}`: a denied module can't have a level"));
+                continue;
+            }
+            if module.contains('/') && !module.trim_start_matches('!').starts_with("file:") {
+                directives.errors.push(format!("`{piece}`: looks like a source file, write `file:{piece}` to match source files"));
+                continue;
+            }
+
+            match level {
+                // a bare level sets the global level, anything else whitelists a module
+                None => match parse_level(module) {
+                    Some(level) => directives.level = Some(level),
+                    None => directives.modules.push((module.to_string(), None)),
+                },
+                Some(level) => match parse_level(level) {
+                    Some(level) => directives.modules.push((module.to_string(), Some(level))),
+                    None => directives.errors.push(format!("`{piece}`: unknown level `{level}`")),
+                },
+            }
+        }
+
+        directives
+    }
+}
+
+/// Parse a level name or its numeric alias, e.g. `debug` or `2`.
+pub(crate) fn parse_level(level: &str) -> Option<LevelFilter> {
+    match level.to_lowercase().as_str() {
+        "1" | "trace" | "all" => Some(LevelFilter::Trace),
+        "2" | "debug" => Some(LevelFilter::Debug),
+        "3" | "info" => Some(LevelFilter::Info),
+        "4" | "warn" => Some(LevelFilter::Warn),
+        "5" | "error" => Some(LevelFilter::Error),
+        "0" | "off" | "disable" | "none" => Some(LevelFilter::Off),
+        _ => None,
+    }
+}
+
+/// Shorten a regex error to a single line; the full message draws the pattern with a caret underneath.
+pub(crate) fn regex_error(error: &regex::Error) -> String {
+    let message: String = error.to_string();
+    let last_line: &str = message.lines().last().unwrap_or_default();
+    last_line.trim_start_matches("error: ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_with(directives: &[(&str, Option<LevelFilter>)]) -> Filter {
+        let mut filter = Filter::new(LevelFilter::Info);
+        for (module, level) in directives {
+            filter.insert(module, *level).unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn most_specific_module_wins() {
+        let filter = filter_with(&[("mycrate", Some(LevelFilter::Trace)), ("mycrate::deserialize", Some(LevelFilter::Warn))]);
+        assert_eq!(filter.level_for("mycrate"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("mycrate::sounds"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("mycrate::deserialize::sounds"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("mycrate_other"), LevelFilter::Off);
+        assert_eq!(filter.level_for("rocket"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn deny_overrides_broader_entries() {
+        let filter = filter_with(&[("rocket", None), ("!rocket::server", None)]);
+        assert_eq!(filter.level_for("rocket::request"), LevelFilter::Info);
+        assert_eq!(filter.level_for("rocket::server::http"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn deny_overrides_broader_globs() {
+        let filter = filter_with(&[("{tokio,hyper,reqwest}", Some(LevelFilter::Debug)), ("!hyper::proto", None)]);
+        assert_eq!(filter.level_for("hyper::client"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("hyper::proto::h1"), LevelFilter::Off);
+
+        let filter = filter_with(&[("**", Some(LevelFilter::Trace)), ("!a", None), ("!mycrate::*::internal", None)]);
+        assert_eq!(filter.level_for("a::b"), LevelFilter::Off);
+        assert_eq!(filter.level_for("mycrate::sounds"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("mycrate::sounds::internal"), LevelFilter::Off);
+
+        // separators inside braces don't make a glob more specific
+        let filter = filter_with(&[("{x::y::z,w}", None), ("!w::q", None)]);
+        assert_eq!(filter.level_for("w::r"), LevelFilter::Info);
+        assert_eq!(filter.level_for("w::q::r"), LevelFilter::Off);
+        let filter = filter_with(&[("{a::b,c}", None), ("!c", None)]);
+        assert_eq!(filter.level_for("a::b::x"), LevelFilter::Info);
+        assert_eq!(filter.level_for("c::x"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn oversized_glob_is_rejected() {
+        let glob: String = "?".repeat(50_000);
+        let mut filter = filter_with(&[("mycrate", None)]);
+        let error: String = filter.insert(&glob, None).unwrap_err();
+        assert!(error.starts_with("invalid glob: "), "{error}");
+        assert_eq!(filter.directives.len(), 1);
+    }
+
+    #[test]
+    fn narrower_glob_overrides_plain_parent() {
+        let filter = filter_with(&[("mycrate", Some(LevelFilter::Warn)), ("*::sounds", Some(LevelFilter::Trace)), ("mycrate::*", Some(LevelFilter::Info))]);
+        assert_eq!(filter.level_for("mycrate"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("mycrate::deserialize"), LevelFilter::Info);
+        assert_eq!(filter.level_for("other::sounds"), LevelFilter::Trace);
+        // equally specific, so the one added last wins
+        assert_eq!(filter.level_for("mycrate::sounds"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn specificity_order() {
+        assert!(specificity("hyper::proto", false) > specificity("{tokio,hyper}", false));
+        assert!(specificity("a", false) > specificity("**", false));
+        assert!(specificity("a::*", false) > specificity("a", false));
+        assert!(specificity("a::b", false) > specificity("a::*", false));
+        assert!(specificity("a::**::c", false) > specificity("a::b", false));
+        assert!(specificity("a::b", false) > specificity("a::{b,c}", false));
+        assert_eq!(specificity("{x::y::z,w}", false), (0, 1, false));
+        assert!(specificity("w::q", false) > specificity("{x::y::z,w}", false));
+        assert_eq!(specificity("src/audio/", true), specificity("src/audio", true));
+        assert!(specificity("src/audio", true) > specificity("src/**/*.rs", true));
+    }
+
+    fn glob_matches(glob: &str, path: &str) -> bool {
+        let file: bool = glob.contains('/');
+        glob_regex(glob, file).unwrap().expect("not a glob").is_match(path)
+    }
+
+    #[test]
+    fn plain_modules_are_not_globs() {
+        assert!(glob_regex("mycrate::sounds", false).unwrap().is_none());
+        assert!(glob_regex("src/audio", true).unwrap().is_none());
+    }
+
+    #[test]
+    fn single_segment_wildcards() {
+        assert!(glob_matches("*::sounds", "mycrate::sounds"));
+        assert!(glob_matches("*::sounds", "mycrate::sounds::parse"));
+        assert!(!glob_matches("*::sounds", "mycrate::deserialize::sounds"));
+        assert!(!glob_matches("*::sounds", "mycrate::soundsystem"));
+        assert!(glob_matches("mycrate::parse_*", "mycrate::parse_sound"));
+        assert!(!glob_matches("mycrate::parse_*", "mycrate::parse::sound_parse_x"));
+        assert!(glob_matches("v?", "v1"));
+        assert!(!glob_matches("v?", "v10"));
+        assert!(!glob_matches("v?", "v"));
+    }
+
+    #[test]
+    fn double_star_spans_segments() {
+        assert!(glob_matches("mycrate::**::parse", "mycrate::parse"));
+        assert!(glob_matches("mycrate::**::parse", "mycrate::a::b::parse::inner"));
+        assert!(!glob_matches("mycrate::**::parse", "mycrate::parser"));
+        assert!(!glob_matches("mycrate::**::parse", "mycrate::a::unparse"));
+        assert!(glob_matches("**", "anything::at::all"));
+        assert!(glob_matches("mycrate::**", "mycrate::a::b"));
+    }
+
+    #[test]
+    fn braces() {
+        assert!(glob_matches("{tokio,hyper}", "tokio::runtime"));
+        assert!(glob_matches("{tokio,hyper}", "hyper"));
+        assert!(!glob_matches("{tokio,hyper}", "tokio_util"));
+        assert!(glob_matches("{a,b{c,d}}::x", "bd::x"));
+        assert!(!glob_matches("{a,b{c,d}}::x", "b::x"));
+        assert!(glob_matches("{*::sounds,audio}", "mycrate::sounds"));
+        // an unclosed brace is taken literally
+        assert!(glob_matches("a{b", "a{b::c"));
+        assert!(!glob_matches("a{b", "ab"));
+    }
+
+    #[test]
+    fn regex_characters_are_literal() {
+        assert!(glob_matches("a.b*", "a.bc"));
+        assert!(!glob_matches("a.b*", "axbc"));
+        assert!(glob_matches("(x)+*", "(x)+y"));
+    }
+
+    #[test]
+    fn file_globs() {
+        assert!(glob_matches("src/**/parse_*.rs", "src/parse_sound.rs"));
+        assert!(glob_matches("src/**/parse_*.rs", "src/a/b/parse_sound.rs"));
+        assert!(!glob_matches("src/**/parse_*.rs", "src/parse_sound.rsx"));
+        assert!(glob_matches("src/*", "src/audio/decode.rs"));
+        assert!(!glob_matches("src/*.rs", "src/audio/decode.rs"));
+        assert!(glob_matches("src/{audio,video}", "src/video/mod.rs"));
+    }
+
+    #[test]
+    fn parse_directives() {
+        let directives = Directives::parse("info, mycrate::sounds=trace ,!rocket::server,rocket,tokio=2");
+        assert_eq!(directives.level, Some(LevelFilter::Info));
+        assert_eq!(directives.modules, vec![
+            ("mycrate::sounds".to_string(), Some(LevelFilter::Trace)),
+            ("!rocket::server".to_string(), None),
+            ("rocket".to_string(), None),
+            ("tokio".to_string(), Some(LevelFilter::Debug)),
+        ]);
+        assert!(directives.include.is_none() && directives.exclude.is_none());
+        assert!(directives.errors.is_empty());
+    }
+
+    #[test]
+    fn parse_directives_with_braces() {
+        let directives = Directives::parse("{tokio,hyper}=debug,mycrate");
+        assert_eq!(directives.modules, vec![
+            ("{tokio,hyper}".to_string(), Some(LevelFilter::Debug)),
+            ("mycrate".to_string(), None),
+        ]);
+    }
+
+    #[test]
+    fn parse_directive_errors() {
+        let directives = Directives::parse("a=b=c,=info,my crate,!x=info,x=loud,,warn");
+        assert_eq!(directives.errors, vec![
+            "`a=b=c`: more than one `=`",
+            "`=info`: missing module name",
+            "`my crate`: whitespace in module name",
+            "`!x=info`: a denied module can't have a level",
+            "`x=loud`: unknown level `loud`",
+        ]);
+        // the rest is still used
+        assert_eq!(directives.level, Some(LevelFilter::Warn));
+        assert!(directives.modules.is_empty());
+    }
+
+    #[test]
+    fn parse_file_entries() {
+        let directives = Directives::parse("info,file:src/audio=trace,!file:src/audio/decode.rs,file:src/{a,b}/*.rs");
+        assert_eq!(directives.level, Some(LevelFilter::Info));
+        assert_eq!(directives.modules, vec![
+            ("file:src/audio".to_string(), Some(LevelFilter::Trace)),
+            ("!file:src/audio/decode.rs".to_string(), None),
+            ("file:src/{a,b}/*.rs".to_string(), None),
+        ]);
+        assert!(directives.include.is_none());
+        assert!(directives.errors.is_empty());
+
+        let directives = Directives::parse("file:src/audio=trace/audio data, length");
+        assert_eq!(directives.modules, vec![("file:src/audio".to_string(), Some(LevelFilter::Trace))]);
+        assert_eq!(directives.include.map(|regex| regex.to_string()).as_deref(), Some("audio data, length"));
+    }
+
+    #[test]
+    fn parse_rejects_file_entries_without_prefix() {
+        let directives = Directives::parse("info,src/audio=trace,mycrate=debug");
+        assert_eq!(directives.level, Some(LevelFilter::Info));
+        assert_eq!(directives.modules, vec![("mycrate".to_string(), Some(LevelFilter::Debug))]);
+        assert!(directives.include.is_none());
+        assert_eq!(directives.errors, vec!["`src/audio=trace`: looks like a source file, write `file:src/audio=trace` to match source files"]);
+
+        // a level in front of the slash is still a message pattern
+        let directives = Directives::parse("mycrate=info/audio=trace");
+        assert_eq!(directives.modules, vec![("mycrate".to_string(), Some(LevelFilter::Info))]);
+        assert_eq!(directives.include.map(|regex| regex.to_string()).as_deref(), Some("audio=trace"));
+    }
+
+    fn file_enabled(filter: &Filter, file: &'static str) -> bool {
+        filter.record_enabled(&log::Record::builder()
+            .level(log::Level::Info)
+            .target("other")
+            .file(Some(file))
+            .build())
+    }
+
+    #[test]
+    fn file_entries() {
+        let mut filter = filter_with(&[("file:build.rs", None), ("src/audio", Some(LevelFilter::Trace)), ("!file:src/audio/decode.rs", None)]);
+        filter.set_match_mode(MatchMode::File);
+        assert!(file_enabled(&filter, "build.rs"));
+        assert!(file_enabled(&filter, "src/audio/mod.rs"));
+        assert!(!file_enabled(&filter, "src/audio/decode.rs"));
+        assert!(!file_enabled(&filter, "src/video/mod.rs"));
+        // file entries never match targets
+        assert_eq!(filter.level_for("build.rs"), LevelFilter::Off);
+        filter.remove("file:src/audio");
+        assert!(!file_enabled(&filter, "src/audio/mod.rs"));
+    }
+
+    #[test]
+    fn parse_message_patterns() {
+        let directives = Directives::parse("info/audio, data length");
+        assert_eq!(directives.level, Some(LevelFilter::Info));
+        assert_eq!(directives.include.map(|regex| regex.to_string()).as_deref(), Some("audio, data length"));
+
+        let directives = Directives::parse("mycrate/!heart/beat");
+        assert_eq!(directives.modules, vec![("mycrate".to_string(), None)]);
+        assert_eq!(directives.exclude.map(|regex| regex.to_string()).as_deref(), Some("heart/beat"));
+
+        let directives = Directives::parse("info/(");
+        assert!(directives.include.is_none());
+        assert_eq!(directives.errors.len(), 1);
+        assert!(directives.errors[0].starts_with("`/(`: invalid regex: "));
+    }
+}
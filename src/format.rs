@@ -0,0 +1,675 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::time::Duration;
+use colored::Color;
+use log::Level;
+use crate::{Entry, Value};
+use crate::color::Painter;
+use crate::timestamp::Timestamp;
+
+pub(crate) const DEFAULT_TEMPLATE: &str = "{?{time} ?}{level} {?@ {location} ?}| {msg}{kv}";
+pub(crate) const DEFAULT_THREAD_TEMPLATE: &str = "{?{time} ?}{level} [{thread}] {?@ {location} ?}| {msg}{kv}";
+
+/// Everything besides the entry itself that a sink needs to render a line.
+pub(crate) struct Context<'a> {
+    pub(crate) painter: Painter,
+    pub(crate) timestamp: &'a Timestamp,
+    /// Time since the sink's previous record, for [`Timestamp::Delta`].
+    pub(crate) since_previous: Duration,
+}
+
+/// How a sink turns records into lines.
+#[derive(Debug, Clone)]
+pub enum Format {
+    /// Human-readable lines laid out by a [`Template`].
+    Text(Template),
+    /// One JSON object per line ([JSON Lines](https://jsonlines.org)), never colored.
+    ///
+    /// `{"timestamp":"2025-04-02T23:14:52.826+02:00","level":"WARN","target":"mycrate::sounds","module_path":"mycrate::sounds",`
+    /// `"file":"src/sounds.rs","line":214,"thread":"main","thread_id":1,"message":"...","fields":{"expected":82734}}`
+    ///
+    /// The timestamp is always RFC 3339, in UTC for `Timestamp::Utc` and `null` for `Timestamp::None`.
+    /// Unknown fields are `null`.
+    /// Key-values of the record are collected in `fields`, with numbers and booleans unquoted.
+    /// Panics also have a `backtrace` array with one string per frame, if one was captured.
+    Json,
+    /// One line of [logfmt](https://brandur.org/logfmt) per record, never colored.
+    ///
+    /// `ts=2025-04-02T23:14:52.826+02:00 level=warn target=mycrate::sounds file=src/sounds.rs line=214 thread=main thread_id=1 msg="..." expected=82734`
+    ///
+    /// The timestamp is RFC 3339 like for [`Format::Json`].
+    /// Values containing spaces, quotes or `=` are quoted; unknown fields are left out.
+    /// Those characters are replaced with `_` in the keys of key-values, which can't be quoted.
+    /// Key-values of the record follow the message, after the `backtrace` of a panic, if one was captured.
+    Logfmt,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Text(Template::default())
+    }
+}
+
+impl Format {
+    pub(crate) fn render(&self, entry: &Entry, context: &Context) -> String {
+        match self {
+            Format::Text(template) => template.render(entry, context),
+            Format::Json => render_json(entry, context),
+            Format::Logfmt => render_logfmt(entry, context),
+        }
+    }
+
+    /// The default human-readable format, optionally with a thread column.
+    pub(crate) fn default_text(show_thread: bool) -> Self {
+        match show_thread {
+            true => Format::Text(Template::parse(DEFAULT_THREAD_TEMPLATE).expect("Default template is invalid")),
+            false => Format::default(),
+        }
+    }
+
+    /// Parse a format name as used in the `<env var>_FORMAT` environment variable.
+    pub(crate) fn from_name(name: &str, show_thread: bool) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "text" | "human" => Some(Format::default_text(show_thread)),
+            "json" | "jsonl" => Some(Format::Json),
+            "logfmt" => Some(Format::Logfmt),
+            _ => None,
+        }
+    }
+}
+
+/// The layout of a human-readable log line.
+///
+/// Fields are written as `{name}` or `{name:spec}`:
+/// - `time`: the timestamp as configured with [`Timestamp`](crate::Timestamp), unknown for `Timestamp::None`;
+///   a spec is a `chrono` strftime pattern overriding it, which is still unknown for `Timestamp::None`
+/// - `level`: the level, colored
+/// - `target`: the record's target, usually the module path
+/// - `module`, `file`, `line`: where the record was logged
+/// - `location`: `module:line`, or whichever of the two is known; `file` stands in for an unknown module
+/// - `thread`: the name of the thread that logged the record, or its ID if it has no name
+/// - `msg`: the message, colored like the level
+/// - `kv`: the record's key-values as dimmed ` key=value` pairs, each with a leading space
+///
+/// Other fields take an alignment spec like `<10` (left), `>5` (right) or `^8` (centered).
+/// Text inside `{? ... ?}` is left out entirely if any field inside it is unknown.
+/// `{{` and `}}` are literal braces.
+///
+/// The default template is `{?{time} ?}{level} {?@ {location} ?}| {msg}{kv}`.
+///
+/// Example use:
+/// ```
+/// let template = biologischer_log::Template::parse("{time:%H:%M:%S%.3f} {level:>5} {target}{? {file}:{line}?} | {msg}").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Field(Field, Spec),
+    Optional(Vec<Part>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Time,
+    Level,
+    Target,
+    Module,
+    File,
+    Line,
+    Location,
+    Thread,
+    Message,
+    KeyValues,
+}
+
+#[derive(Debug, Clone, Default)]
+enum Spec {
+    #[default]
+    None,
+    Align(Alignment, usize),
+    TimeFormat(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+/// Returned by [`Template::parse`] for a malformed template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    message: String,
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid log template: {}", self.message)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl Default for Template {
+    fn default() -> Self {
+        Template::parse(DEFAULT_TEMPLATE).expect("Default template is invalid")
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        Template::parse(template)
+    }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, TemplateError> {
+        let mut chars = template.chars().peekable();
+        let parts: Vec<Part> = parse_parts(&mut chars, false)?;
+        Ok(Template { parts })
+    }
+
+    pub(crate) fn render(&self, entry: &Entry, context: &Context) -> String {
+        let mut line = String::new();
+        render_parts(&self.parts, entry, context, &mut line);
+        line
+    }
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn error(message: String) -> TemplateError {
+    TemplateError { message }
+}
+
+/// Parse until the end of the template, or until `?}` when inside an optional section.
+fn parse_parts(chars: &mut Chars, optional: bool) -> Result<Vec<Part>, TemplateError> {
+    let mut parts: Vec<Part> = Vec::new();
+    let mut literal = String::new();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '{' if chars.peek() == Some(&'?') => {
+                chars.next();
+                flush_literal(&mut literal, &mut parts);
+                parts.push(Part::Optional(parse_parts(chars, true)?));
+            }
+            '{' => {
+                flush_literal(&mut literal, &mut parts);
+                parts.push(parse_field(chars)?);
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(error("unmatched `}`, use `}}` for a literal brace".to_string())),
+            '?' if optional && chars.peek() == Some(&'}') => {
+                chars.next();
+                flush_literal(&mut literal, &mut parts);
+                return Ok(parts);
+            }
+            _ => literal.push(char),
+        }
+    }
+
+    if optional {
+        return Err(error("unclosed optional section, expected `?}`".to_string()));
+    }
+    flush_literal(&mut literal, &mut parts);
+    Ok(parts)
+}
+
+fn flush_literal(literal: &mut String, parts: &mut Vec<Part>) {
+    if !literal.is_empty() {
+        parts.push(Part::Literal(std::mem::take(literal)));
+    }
+}
+
+fn parse_field(chars: &mut Chars) -> Result<Part, TemplateError> {
+    let mut content = String::new();
+    loop {
+        match chars.next() {
+            Some('}') => break,
+            Some(char) => content.push(char),
+            None => return Err(error(format!("unclosed field `{{{content}`"))),
+        }
+    }
+
+    let (name, spec): (&str, Option<&str>) = match content.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec)),
+        None => (content.trim(), None),
+    };
+    let field: Field = match name {
+        "time" => Field::Time,
+        "level" => Field::Level,
+        "target" => Field::Target,
+        "module" => Field::Module,
+        "file" => Field::File,
+        "line" => Field::Line,
+        "location" => Field::Location,
+        "thread" => Field::Thread,
+        "msg" | "message" => Field::Message,
+        "kv" => Field::KeyValues,
+        _ => return Err(error(format!("unknown field `{name}`"))),
+    };
+
+    let spec: Spec = match spec {
+        None => Spec::None,
+        Some(spec) if field == Field::Time => Spec::TimeFormat(spec.to_string()),
+        Some(spec) => parse_alignment(spec).ok_or_else(|| error(format!("invalid alignment `{spec}` for `{name}`")))?,
+    };
+    Ok(Part::Field(field, spec))
+}
+
+fn parse_alignment(spec: &str) -> Option<Spec> {
+    let (alignment, width): (Alignment, &str) = match spec.chars().next()? {
+        '<' => (Alignment::Left, &spec[1..]),
+        '>' => (Alignment::Right, &spec[1..]),
+        '^' => (Alignment::Center, &spec[1..]),
+        _ => (Alignment::Left, spec),
+    };
+    Some(Spec::Align(alignment, width.parse().ok()?))
+}
+
+/// Returns `false` if a field was unknown, so an enclosing optional section can be left out.
+fn render_parts(parts: &[Part], entry: &Entry, context: &Context, out: &mut String) -> bool {
+    let mut complete: bool = true;
+    for part in parts {
+        match part {
+            Part::Literal(literal) => out.push_str(literal),
+            Part::Optional(parts) => {
+                let mut section = String::new();
+                if render_parts(parts, entry, context, &mut section) {
+                    out.push_str(&section);
+                }
+            }
+            Part::Field(field, spec) => {
+                let value: Option<Cow<str>> = match spec {
+                    Spec::TimeFormat(pattern) => context.timestamp.render_pattern(entry, pattern).map(Cow::Owned),
+                    _ => field_value(*field, entry, context),
+                };
+                let Some(value) = value else {
+                    complete = false;
+                    continue;
+                };
+                // pad before painting so the escape codes don't count towards the width
+                let value: Cow<str> = match spec {
+                    Spec::Align(alignment, width) => Cow::Owned(align(&value, *alignment, *width)),
+                    _ => value,
+                };
+                match field {
+                    Field::Level | Field::Message => out.push_str(&context.painter.paint(&value, level_color(entry.level))),
+                    Field::KeyValues => out.push_str(&context.painter.dim(&value)),
+                    _ => out.push_str(&value),
+                }
+            }
+        }
+    }
+    complete
+}
+
+fn field_value<'a>(field: Field, entry: &'a Entry, context: &Context) -> Option<Cow<'a, str>> {
+    match field {
+        Field::Time => context.timestamp.render(entry, context.since_previous).map(Cow::Owned),
+        Field::Level => Some(Cow::Borrowed(entry.level.as_str())),
+        Field::Target => Some(Cow::Borrowed(&entry.target)),
+        Field::Module => entry.module_path.as_deref().map(Cow::Borrowed),
+        Field::File => entry.file.as_deref().map(Cow::Borrowed),
+        Field::Line => entry.line.map(|line| Cow::Owned(line.to_string())),
+        // records without a module, e.g. panics, are located by their file instead
+        Field::Location => match (entry.module_path.as_ref().or(entry.file.as_ref()), entry.line) {
+            (Some(module_path), Some(line_number)) => Some(Cow::Owned(format!("{module_path}:{line_number}"))),
+            (Some(module_path), None) => Some(Cow::Borrowed(module_path)),
+            (None, Some(line_number)) => Some(Cow::Owned(line_number.to_string())),
+            (None, None) => None,
+        },
+        Field::Thread => match &entry.thread_name {
+            Some(name) => Some(Cow::Borrowed(name)),
+            None => Some(Cow::Owned(format!("ThreadId({})", entry.thread_id))),
+        },
+        Field::Message => Some(Cow::Borrowed(&entry.message)),
+        Field::KeyValues => {
+            let mut pairs = String::new();
+            for (key, value) in &entry.key_values {
+                logfmt_field(&mut pairs, key, Some(&value.to_string()));
+            }
+            // the first pair needs a separator from the message too
+            if !pairs.is_empty() {
+                pairs.insert(0, ' ');
+            }
+            Some(Cow::Owned(pairs))
+        }
+    }
+}
+
+fn align(value: &str, alignment: Alignment, width: usize) -> String {
+    let mut aligned = String::new();
+    match alignment {
+        Alignment::Left => write!(aligned, "{value:<width$}"),
+        Alignment::Right => write!(aligned, "{value:>width$}"),
+        Alignment::Center => write!(aligned, "{value:^width$}"),
+    }.expect("Writing to a String can't fail");
+    aligned
+}
+
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::Red,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::Green,
+        Level::Debug => Color::Cyan,
+        Level::Trace => Color::White,
+    }
+}
+
+fn render_json(entry: &Entry, context: &Context) -> String {
+    let mut json = String::from("{");
+    json_field(&mut json, "timestamp", context.timestamp.render_rfc3339(entry).as_deref());
+    json_field(&mut json, "level", Some(entry.level.as_str()));
+    json_field(&mut json, "target", Some(&entry.target));
+    json_field(&mut json, "module_path", entry.module_path.as_deref());
+    json_field(&mut json, "file", entry.file.as_deref());
+    json_raw_field(&mut json, "line", entry.line.map(|line| line.to_string()).as_deref());
+    json_field(&mut json, "thread", entry.thread_name.as_deref());
+    json_raw_field(&mut json, "thread_id", Some(&entry.thread_id.to_string()));
+    json_field(&mut json, "message", Some(&entry.message));
+    if let Some(backtrace) = &entry.backtrace {
+        let mut frames = String::from("[");
+        for (index, frame) in backtrace.iter().enumerate() {
+            if index > 0 {
+                frames.push(',');
+            }
+            json_string(&mut frames, frame);
+        }
+        frames.push(']');
+        json_raw_field(&mut json, "backtrace", Some(&frames));
+    }
+
+    let mut fields = String::from("{");
+    for (key, value) in &entry.key_values {
+        let value: String = match value {
+            Value::Bool(value) => value.to_string(),
+            Value::Number(value) => value.clone(),
+            Value::Text(value) => {
+                let mut string = String::new();
+                json_string(&mut string, value);
+                string
+            }
+        };
+        json_raw_field(&mut fields, key, Some(&value));
+    }
+    fields.push('}');
+    json_raw_field(&mut json, "fields", Some(&fields));
+
+    json.push('}');
+    json
+}
+
+fn json_field(json: &mut String, key: &str, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            let mut string = String::new();
+            json_string(&mut string, value);
+            json_raw_field(json, key, Some(&string));
+        }
+        None => json_raw_field(json, key, None),
+    }
+}
+
+/// Add a field whose value is already valid JSON, or `null`.
+fn json_raw_field(json: &mut String, key: &str, value: Option<&str>) {
+    if json.len() > 1 {
+        json.push(',');
+    }
+    json_string(json, key);
+    json.push(':');
+    json.push_str(value.unwrap_or("null"));
+}
+
+fn json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            char if char.is_control() => write!(json, "\\u{:04x}", char as u32).expect("Writing to a String can't fail"),
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+}
+
+fn render_logfmt(entry: &Entry, context: &Context) -> String {
+    let mut logfmt = String::new();
+    logfmt_field(&mut logfmt, "ts", context.timestamp.render_rfc3339(entry).as_deref());
+    logfmt_field(&mut logfmt, "level", Some(&entry.level.as_str().to_lowercase()));
+    logfmt_field(&mut logfmt, "target", Some(&entry.target));
+    logfmt_field(&mut logfmt, "file", entry.file.as_deref());
+    logfmt_field(&mut logfmt, "line", entry.line.map(|line| line.to_string()).as_deref());
+    logfmt_field(&mut logfmt, "thread", entry.thread_name.as_deref());
+    logfmt_field(&mut logfmt, "thread_id", Some(&entry.thread_id.to_string()));
+    logfmt_field(&mut logfmt, "msg", Some(&entry.message));
+    logfmt_field(&mut logfmt, "backtrace", entry.backtrace.as_ref().map(|frames| frames.join("\n")).as_deref());
+    for (key, value) in &entry.key_values {
+        logfmt_field(&mut logfmt, key, Some(&value.to_string()));
+    }
+    logfmt
+}
+
+fn logfmt_field(logfmt: &mut String, key: &str, value: Option<&str>) {
+    let Some(value) = value else {
+        return;
+    };
+    if !logfmt.is_empty() {
+        logfmt.push(' ');
+    }
+    // keys can't be quoted, so characters that would end them early are replaced
+    if key.is_empty() {
+        logfmt.push('_');
+    }
+    logfmt.extend(key.chars().map(|char| if logfmt_special(char) { '_' } else { char }));
+    logfmt.push('=');
+
+    let needs_quotes: bool = value.is_empty() || value.chars().any(logfmt_special);
+    if !needs_quotes {
+        logfmt.push_str(value);
+        return;
+    }
+    logfmt.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => logfmt.push_str("\\\""),
+            '\\' => logfmt.push_str("\\\\"),
+            '\n' => logfmt.push_str("\\n"),
+            '\r' => logfmt.push_str("\\r"),
+            '\t' => logfmt.push_str("\\t"),
+            char if char.is_control() => write!(logfmt, "\\u{:04x}", char as u32).expect("Writing to a String can't fail"),
+            char => logfmt.push(char),
+        }
+    }
+    logfmt.push('"');
+}
+
+/// Characters that can't appear in a logfmt key or an unquoted value.
+fn logfmt_special(char: char) -> bool {
+    char <= ' ' || char == '=' || char == '"' || char == '\\' || char.is_control()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry() -> Entry {
+        Entry {
+            time: chrono::Local.with_ymd_and_hms(2025, 4, 2, 23, 14, 52).unwrap(),
+            instant: std::time::Instant::now(),
+            level: Level::Warn,
+            target: "mycrate::sounds".to_string(),
+            module_path: Some("mycrate::sounds".to_string()),
+            file: Some("src/sounds.rs".to_string()),
+            line: Some(214),
+            thread_name: Some("main".to_string()),
+            thread_id: 1,
+            message: "length mismatch".to_string(),
+            key_values: Vec::new(),
+            backtrace: None,
+            panic: false,
+        }
+    }
+
+    fn render(template: &str, entry: &Entry, timestamp: &Timestamp) -> String {
+        let context = Context {
+            painter: Painter(false),
+            timestamp,
+            since_previous: Duration::ZERO,
+        };
+        Template::parse(template).unwrap().render(entry, &context)
+    }
+
+    fn parse_error(template: &str) -> String {
+        Template::parse(template).unwrap_err().message
+    }
+
+    #[test]
+    fn default_template() {
+        let line: String = render(DEFAULT_TEMPLATE, &entry(), &Timestamp::default());
+        assert_eq!(line, "23:14:52.000 WARN @ mycrate::sounds:214 | length mismatch");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(render("{{{level}}} }}{{", &entry(), &Timestamp::None), "{WARN} }{");
+    }
+
+    #[test]
+    fn optional_sections() {
+        let mut entry: Entry = entry();
+        assert_eq!(render("{level}{? {module}:{line}?} |", &entry, &Timestamp::None), "WARN mycrate::sounds:214 |");
+        entry.line = None;
+        assert_eq!(render("{level}{? {module}:{line}?} |", &entry, &Timestamp::None), "WARN |");
+        // an unknown field only removes the innermost section around it
+        assert_eq!(render("{?<{module}{? {line}?}>?}", &entry, &Timestamp::None), "<mycrate::sounds>");
+    }
+
+    #[test]
+    fn time_is_unknown_without_timestamp() {
+        assert_eq!(render("{?{time} ?}{level}", &entry(), &Timestamp::None), "WARN");
+        assert_eq!(render("{?{time:%H:%M} ?}{level}", &entry(), &Timestamp::None), "WARN");
+        assert_eq!(render("{?{time:%H:%M} ?}{level}", &entry(), &Timestamp::default()), "23:14 WARN");
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(render("[{level:<6}]", &entry(), &Timestamp::None), "[WARN  ]");
+        assert_eq!(render("[{level:>6}]", &entry(), &Timestamp::None), "[  WARN]");
+        assert_eq!(render("[{level:^8}]", &entry(), &Timestamp::None), "[  WARN  ]");
+        assert_eq!(render("[{level:6}]", &entry(), &Timestamp::None), "[WARN  ]");
+        // values longer than the width are never cut off
+        assert_eq!(render("[{target:>4}]", &entry(), &Timestamp::None), "[mycrate::sounds]");
+    }
+
+    #[test]
+    fn location_falls_back_to_file() {
+        let mut entry: Entry = entry();
+        entry.module_path = None;
+        assert_eq!(render("{location}", &entry, &Timestamp::None), "src/sounds.rs:214");
+    }
+
+    #[test]
+    fn logfmt_values() {
+        let mut logfmt = String::new();
+        logfmt_field(&mut logfmt, "plain", Some("value"));
+        logfmt_field(&mut logfmt, "missing", None);
+        logfmt_field(&mut logfmt, "empty", Some(""));
+        logfmt_field(&mut logfmt, "space", Some("two words"));
+        logfmt_field(&mut logfmt, "equals", Some("a=b"));
+        logfmt_field(&mut logfmt, "quote", Some("say \"hi\""));
+        logfmt_field(&mut logfmt, "backslash", Some("C:\\dir"));
+        logfmt_field(&mut logfmt, "lines", Some("one\ntwo\tthree\r"));
+        logfmt_field(&mut logfmt, "control", Some("\u{1b}[0m"));
+        logfmt_field(&mut logfmt, "unicode", Some("grüße"));
+        assert_eq!(
+            logfmt,
+            r#"plain=value empty="" space="two words" equals="a=b" quote="say \"hi\"" backslash="C:\\dir" lines="one\ntwo\tthree\r" control="\u001b[0m" unicode=grüße"#,
+        );
+    }
+
+    #[test]
+    fn logfmt_keys() {
+        let mut logfmt = String::new();
+        logfmt_field(&mut logfmt, "user id", Some("1"));
+        logfmt_field(&mut logfmt, "a=b", Some("2"));
+        logfmt_field(&mut logfmt, "\"quoted\"", Some("3"));
+        logfmt_field(&mut logfmt, "", Some("4"));
+        logfmt_field(&mut logfmt, "user.name", Some("5"));
+        assert_eq!(logfmt, "user_id=1 a_b=2 _quoted_=3 _=4 user.name=5");
+    }
+
+    #[test]
+    fn logfmt_record() {
+        let mut entry: Entry = entry();
+        entry.key_values = vec![("bad key".to_string(), Value::Text("x y".to_string())), ("n".to_string(), Value::Number("3".to_string()))];
+        let context = Context {
+            painter: Painter(false),
+            timestamp: &Timestamp::None,
+            since_previous: Duration::ZERO,
+        };
+        assert_eq!(
+            render_logfmt(&entry, &context),
+            r#"level=warn target=mycrate::sounds file=src/sounds.rs line=214 thread=main thread_id=1 msg="length mismatch" bad_key="x y" n=3"#,
+        );
+    }
+
+    #[test]
+    fn json_record() {
+        let mut entry: Entry = entry();
+        entry.module_path = None;
+        entry.line = None;
+        entry.message = "say \"hi\"\\\n\tnow\u{1}".to_string();
+        entry.key_values = vec![
+            ("n".to_string(), Value::Number("-3.5".to_string())),
+            ("ok".to_string(), Value::Bool(true)),
+            ("name".to_string(), Value::Text("a\"b".to_string())),
+        ];
+        entry.backtrace = Some(vec!["mycrate::main at ./src/main.rs:3:24".to_string()]);
+        let context = Context {
+            painter: Painter(false),
+            timestamp: &Timestamp::None,
+            since_previous: Duration::ZERO,
+        };
+        assert_eq!(
+            render_json(&entry, &context),
+            concat!(
+                r#"{"timestamp":null,"level":"WARN","target":"mycrate::sounds","module_path":null,"file":"src/sounds.rs","line":null,"#,
+                r#""thread":"main","thread_id":1,"message":"say \"hi\"\\\n\tnow\u0001","#,
+                r#""backtrace":["mycrate::main at ./src/main.rs:3:24"],"fields":{"n":-3.5,"ok":true,"name":"a\"b"}}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error("{level} }"), "unmatched `}`, use `}}` for a literal brace");
+        assert_eq!(parse_error("{? {level}"), "unclosed optional section, expected `?}`");
+        assert_eq!(parse_error("{level"), "unclosed field `{level`");
+        assert_eq!(parse_error("{lvl}"), "unknown field `lvl`");
+        assert_eq!(parse_error("{level:>x}"), "invalid alignment `>x` for `level`");
+        assert_eq!(parse_error("{level:}"), "invalid alignment `` for `level`");
+    }
+}
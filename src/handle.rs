@@ -0,0 +1,177 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use arc_swap::ArcSwap;
+use log::{Level, LevelFilter, Metadata, Record};
+use crate::Entry;
+use crate::color::ColorChoice;
+use crate::filter::{Filter, MatchMode};
+use crate::format::Format;
+use crate::output::{Sink, Sinks};
+use crate::timestamp::Timestamp;
+use crate::worker::{LogWorker, Overflow};
+
+/// The settings `log()` reads on every call. Never modified in place, but replaced as a whole,
+/// so reading them only costs an atomic load.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) filter: Filter,
+    /// The most verbose level any sink accepts; records above it would be thrown away anyway.
+    pub(crate) sink_level: LevelFilter,
+    pub(crate) overflow: Overflow,
+}
+
+impl Config {
+    pub(crate) fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.level() > self.sink_level {
+            return false;
+        }
+        // allow if the most specific whitelisted parent module allows this level
+        self.filter.enabled(metadata)
+    }
+
+    /// Like [`Config::enabled`], but also matches the module path or file if the [`MatchMode`](crate::MatchMode) asks for it.
+    pub(crate) fn record_enabled(&self, record: &Record) -> bool {
+        record.level() <= self.sink_level && self.filter.record_enabled(record)
+    }
+
+    /// The level for `log::set_max_level`.
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        self.filter.max_level().min(self.sink_level)
+    }
+}
+
+/// The builder's settings for sinks that don't have their own, kept for sinks added later.
+pub(crate) struct SinkDefaults {
+    pub(crate) color: ColorChoice,
+    pub(crate) timestamp: Timestamp,
+    pub(crate) format: Format,
+}
+
+/// Everything shared between a logger, its guard and its handles.
+pub(crate) struct Shared {
+    pub(crate) config: ArcSwap<Config>,
+    pub(crate) worker: Arc<LogWorker>,
+    pub(crate) defaults: SinkDefaults,
+    /// Set once the logger is installed with `log`, whose max level then has to follow every change.
+    pub(crate) installed: AtomicBool,
+    /// Serializes changes so none of them get lost; readers never take it.
+    update: Mutex<()>,
+}
+
+impl Shared {
+    pub(crate) fn new(config: Config, worker: Arc<LogWorker>, defaults: SinkDefaults) -> Self {
+        Shared {
+            config: ArcSwap::from_pointee(config),
+            worker,
+            defaults,
+            installed: AtomicBool::new(false),
+            update: Mutex::new(()),
+        }
+    }
+
+    /// Report a problem with the configuration in the log itself, regardless of the filter.
+    pub(crate) fn warn(&self, message: String) {
+        self.worker.write(Entry::internal(Level::Warn, message), self.config.load().overflow);
+    }
+
+    /// Fill in the settings the sinks don't have themselves with the builder's.
+    pub(crate) fn resolve_sinks(&self, sinks: Vec<Sink>) -> Sinks {
+        let mut sinks: Sinks = Sinks(sinks);
+        for sink in &mut sinks.0 {
+            sink.resolve(self.defaults.color, &self.defaults.timestamp, &self.defaults.format);
+        }
+        sinks
+    }
+
+    /// Replace the config with a changed copy.
+    pub(crate) fn update(&self, change: impl FnOnce(&mut Config)) {
+        let _update: MutexGuard<()> = self.update.lock().expect("Could not lock logger config");
+        let mut config: Config = Config::clone(&self.config.load());
+        change(&mut config);
+        if self.installed.load(Ordering::Relaxed) {
+            log::set_max_level(config.max_level());
+        }
+        self.config.store(Arc::new(config));
+    }
+}
+
+/// Changes the configuration of a running logger, e.g. from a debug endpoint.
+/// Obtained with [`LoggerGuard::handle`](crate::LoggerGuard::handle) or [`BioLogger::handle`](crate::BioLogger::handle)
+/// and cheap to clone. Changes apply to every record logged afterwards, without a restart.
+///
+/// Example use:
+/// ```no_run
+/// use log::LevelFilter;
+///
+/// let logger = biologischer_log::init(env!("CARGO_CRATE_NAME"));
+/// let handle = logger.handle();
+/// std::thread::spawn(move || {
+///     handle.set_module_level("mycrate::deserialize", LevelFilter::Trace);
+///     handle.remove_module("rocket");
+/// });
+/// ```
+#[derive(Clone)]
+pub struct LogHandle {
+    shared: Arc<Shared>,
+}
+
+impl LogHandle {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        LogHandle { shared }
+    }
+
+    /// Allow logs from this module and all of its submodules at the global level.
+    /// `!module` denies it instead.
+    pub fn whitelist_module(&self, module: &str) {
+        self.insert(module, None);
+    }
+
+    /// Mute this module and all of its submodules, even if a parent module is whitelisted.
+    pub fn deny_module(&self, module: &str) {
+        self.set_module_level(module, LevelFilter::Off);
+    }
+
+    /// Allow logs from this module and all of its submodules up to `level`.
+    pub fn set_module_level(&self, module: &str, level: LevelFilter) {
+        self.insert(module, Some(level));
+    }
+
+    /// A glob that can't be compiled is reported in the log, like a malformed directive at startup.
+    fn insert(&self, module: &str, level: Option<LevelFilter>) {
+        let mut result: Result<(), String> = Ok(());
+        self.shared.update(|config| result = config.filter.insert(module, level));
+        if let Err(error) = result {
+            self.shared.warn(format!("Ignoring malformed filter directive `{module}`: {error}"));
+        }
+    }
+
+    /// Remove the entry for exactly this module, whitelisted or denied.
+    /// It then follows its closest parent entry again, and is muted if there is none.
+    pub fn remove_module(&self, module: &str) {
+        self.shared.update(|config| config.filter.remove(module));
+    }
+
+    /// Set the level of whitelisted modules that don't have their own.
+    pub fn set_level(&self, level: LevelFilter) {
+        self.shared.update(|config| config.filter.set_level(level));
+    }
+
+    /// Choose what the whitelist is matched against, see [`MatchMode`].
+    pub fn set_match_mode(&self, mode: MatchMode) {
+        self.shared.update(|config| config.filter.set_match_mode(mode));
+    }
+
+    /// Choose what happens when messages are logged faster than they can be written.
+    pub fn set_overflow(&self, overflow: Overflow) {
+        self.shared.update(|config| config.overflow = overflow);
+    }
+
+    /// Replace all sinks. Messages still queued are written to the new ones.
+    /// Settings the sinks don't have themselves are taken from the [`Builder`](crate::Builder) as usual.
+    pub fn set_sinks(&self, sinks: Vec<Sink>) {
+        let sinks: Sinks = self.shared.resolve_sinks(sinks);
+        let sink_level: LevelFilter = sinks.max_level();
+        self.shared.worker.replace_sinks(sinks);
+        self.shared.update(|config| config.sink_level = sink_level);
+    }
+}
@@ -1,161 +1,292 @@
-use std::thread;
-use std::sync::{Arc, Mutex, MutexGuard};
-use std::collections::HashSet;
-use std::io::Write;
-use std::thread::Thread;
+mod builder;
+mod color;
+mod config;
+mod file;
+mod filter;
+mod format;
+mod handle;
+mod output;
+mod panic;
+mod timestamp;
+mod worker;
+
+use std::sync::Arc;
+use std::thread::{Thread, ThreadId};
+use std::time::Instant;
 use log::{Level, LevelFilter};
-use colored::{Color, Colorize};
+use crate::handle::Shared;
+
+pub use crate::builder::Builder;
+pub use crate::file::{reopen_files, FileOutput};
+pub use crate::filter::MatchMode;
+pub use crate::color::ColorChoice;
+pub use crate::format::{Format, Template, TemplateError};
+pub use crate::handle::LogHandle;
+pub use crate::output::{Output, Sink};
+pub use crate::timestamp::Timestamp;
+pub use crate::worker::Overflow;
+
+/// A log record captured on the calling thread, waiting to be written by the worker thread.
+pub(crate) struct Entry {
+    time: chrono::DateTime<chrono::Local>,
+    /// Taken together with `time`, for timestamps that must not jump with the wall clock.
+    instant: Instant,
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    thread_name: Option<String>,
+    thread_id: u64,
+    message: String,
+    key_values: Vec<(String, Value)>,
+    /// The filtered stack of a panic, one `symbol at file:line:column` per frame.
+    backtrace: Option<Vec<String>>,
+    /// Set for the record written by the panic hook, which console text sinks leave to the panic banner.
+    panic: bool,
+}
+
+/// A structured value from `log`'s key-value records, kept apart so JSON can write numbers unquoted.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Bool(bool),
+    /// An integer or finite float, already formatted.
+    Number(String),
+    Text(String),
+}
 
-struct LogWorker {
-    handle: Option<thread::JoinHandle<()>>,
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Number(value) | Value::Text(value) => f.write_str(value),
+        }
+    }
+}
+
+/// Copies the key-values of a record so they can be sent to the worker thread.
+struct KeyValueCollector(Vec<(String, Value)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValueCollector {
+    fn visit_pair(&mut self, key: log::kv::Key<'kvs>, value: log::kv::Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value: Value = if let Some(value) = value.to_bool() {
+            Value::Bool(value)
+        } else if value.to_i64().is_some() || value.to_u64().is_some() || value.to_f64().is_some_and(f64::is_finite) {
+            Value::Number(value.to_string())
+        } else {
+            Value::Text(value.to_string())
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+impl Entry {
+    fn capture(record: &log::Record, message: String) -> Self {
+        let thread: Thread = std::thread::current();
+        Entry {
+            time: chrono::Local::now(),
+            instant: Instant::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+            thread_name: thread.name().map(str::to_string),
+            thread_id: thread_id(),
+            message,
+            key_values: capture_key_values(record.key_values()),
+            backtrace: None,
+            panic: false,
+        }
+    }
+
+    /// An entry produced by the logger itself rather than by a `log` macro.
+    fn internal(level: Level, message: String) -> Self {
+        let thread: Thread = std::thread::current();
+        Entry {
+            time: chrono::Local::now(),
+            instant: Instant::now(),
+            level,
+            target: module_path!().to_string(),
+            module_path: Some(module_path!().to_string()),
+            file: None,
+            line: None,
+            thread_name: thread.name().map(str::to_string),
+            thread_id: thread_id(),
+            message,
+            key_values: Vec::new(),
+            backtrace: None,
+            panic: false,
+        }
+    }
+}
+
+fn capture_key_values(source: &dyn log::kv::Source) -> Vec<(String, Value)> {
+    let mut collector = KeyValueCollector(Vec::with_capacity(source.count()));
+    // the collector never fails, and a misbehaving source shouldn't lose the message
+    source.visit(&mut collector).ok();
+    collector.0
+}
+
+thread_local! {
+    /// A thread's ID never changes, so it is only formatted and parsed once per thread.
+    static THREAD_ID: u64 = parse_thread_id(std::thread::current().id());
+}
+
+/// The numeric part of the calling thread's `ThreadId`.
+fn thread_id() -> u64 {
+    // thread locals are gone while the thread is shutting down
+    THREAD_ID.try_with(|id| *id).unwrap_or_else(|_| parse_thread_id(std::thread::current().id()))
+}
+
+/// `ThreadId::as_u64` is still unstable.
+fn parse_thread_id(id: ThreadId) -> u64 {
+    let id: String = format!("{id:?}");
+    id.chars().filter(char::is_ascii_digit).collect::<String>().parse().unwrap_or_default()
 }
 
 pub struct BioLogger {
-    worker: Arc<Mutex<LogWorker>>,
-    whitelist: HashSet<String>,
+    shared: Arc<Shared>,
 }
 
 
+impl Default for BioLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BioLogger {
+    /// Create a logger with the default configuration and the panic hook installed.
+    /// Nothing is whitelisted yet.
     pub fn new() -> Self {
-        let messages: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
-        let worker: Arc<Mutex<LogWorker>> = Arc::new(Mutex::new(LogWorker {
-            handle: None,
-        }));
-
-        let thread_handle = thread::spawn(move || {
-            loop {
-                let mut messages: MutexGuard<Vec<String>> = messages.lock().expect("Could not lock messages");
-                for message in messages.drain(..) {
-                    println!("{}", message);
-                }
-            }
-        });
-
-        worker.lock().expect("Could not lock log worker").handle = Some(thread_handle);
-
-        // Hook into process exit
-        let logger = BioLogger {
-            worker,
-            whitelist: HashSet::new(),
-        };
-        logger.install_panic_hook();
-        logger
-    }
-
-    fn install_panic_hook(&self) {
-        std::panic::set_hook(Box::new(|info| {
-            // handle both &str and String payload types
-            let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
-                *s
-            } else if let Some(s) = info.payload().downcast_ref::<String>() {
-                s.as_str()
-            } else {
-                "<panic>"
-            };
-
-            let location = info.location().map(|l| {
-                format!("{}:{}:{}", l.file(), l.line(), l.column())
-            }).unwrap_or_else(|| "<unknown>".to_string());
-            
-            let thread: Thread = std::thread::current();
-            let thread_name: String = match thread.name() {
-                Some(name) => name.to_string(),
-                None => format!("{:?}", thread.id()),
-            };
-            
-            // Direct write to stderr
-            let bullet = ">".red();
-            let line1 = "========== Rust panicked! ==========".red();
-            let line2 = format!("{} {} {}", bullet, "Thread:".bright_red(), thread_name.bright_yellow());
-            let line3 = format!("{} {} {}", bullet, "Location:".bright_red(), location.bright_yellow());
-            let line4 = format!("{} {} {}", bullet, "Message:".bright_red(), message.bright_yellow());
-            let output = format!("{line1}\n{line2}\n{line3}\n{line4}\n");
-            eprintln!("{output}");
-            std::io::stderr().flush().ok();
-            std::io::stdout().flush().ok();
-        }));
+        Self::builder().build()
     }
 
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// A handle to change the configuration later, even after the logger has been installed.
+    pub fn handle(&self) -> LogHandle {
+        LogHandle::new(self.shared.clone())
+    }
 
+    /// Allow logs from this module and all of its submodules at the global level.
     pub fn whitelist_module(&mut self, module: &str) {
-        self.whitelist.insert(module.to_string());
+        self.handle().whitelist_module(module);
+    }
+
+    /// Allow logs from this module and all of its submodules up to `level`.
+    /// The most specific module wins, so `mycrate::deserialize` can be quieter than `mycrate`.
+    pub fn set_module_level(&mut self, module: &str, level: LevelFilter) {
+        self.handle().set_module_level(module, level);
+    }
+
+    /// Choose what happens when messages are logged faster than they can be written.
+    /// Dropped messages are counted and reported periodically by the logging thread.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.handle().set_overflow(overflow);
     }
 }
 
 impl log::Log for BioLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        let target: &str = metadata.target();
-
-        // allow if any parent module is whitelisted
-        self.whitelist.iter().any(|whitelisted| {
-            target.starts_with(whitelisted) &&
-                (target.len() == whitelisted.len() || target.as_bytes()[whitelisted.len()] == b':') 
-        })
+        self.shared.config.load().enabled(metadata)
     }
 
     fn log(&self, record: &log::Record) {
-        if !self.enabled(record.metadata()) {
+        // one snapshot for the whole call, so a concurrent change can't apply halfway
+        let config = self.shared.config.load();
+        if !config.record_enabled(record) {
             return;
         }
 
-        let color: Color = match record.level() {
-            Level::Error => Color::Red,
-            Level::Warn => Color::Yellow,
-            Level::Info => Color::Green,
-            Level::Debug => Color::Cyan,
-            Level::Trace => Color::White,
-        };
-
-        let target: &String = match (record.module_path(), record.line()) {
-            (Some(module_path), Some(line_number)) => &format!("@ {module_path}:{line_number} "),
-            (Some(module_path), None) => &format!("@ {module_path} "),
-            (None, Some(line_number)) => &format!("@ {line_number} "),
-            (None, None) => &"".to_string(),
-        };
+        let message: String = record.args().to_string();
+        if !config.filter.message_allowed(&message) {
+            return;
+        }
 
-        println!(
-            "{} {} {}| {}",
-            chrono::Local::now().format("%H:%M:%S%.3f").to_string(),
-            record.level().to_string().color(color),
-            target,
-            record.args().to_string().color(color),
-        );
+        self.shared.worker.write(Entry::capture(record, message), config.overflow);
     }
 
-    fn flush(&self) {}
+    /// Wait until every queued message has been written and the outputs are flushed.
+    fn flush(&self) {
+        self.shared.worker.flush(None);
+    }
 }
 
 impl Drop for BioLogger {
     fn drop(&mut self) {
-        self.worker.lock().expect("Could not lock worker").handle.take();
+        self.shared.worker.shutdown();
     }
 }
 
 
-/// Initialize the logger. This function should be called once at the start of your main function.
-/// 
-/// Example use: `biologischer_log::init(env!("CARGO_CRATE_NAME"))`
-pub fn init(crate_name: &str) {
-    let level_filter: String = std::env::var("BIO_LOG").unwrap_or_default().to_lowercase();
-    let level_filter: LevelFilter = match level_filter.as_str() {
-        "1" | "trace" | "all" => LevelFilter::Trace,
-        "2" | "debug" => LevelFilter::Debug,
-        "3" | "info" => LevelFilter::Info,
-        "4" | "warn" => LevelFilter::Warn,
-        "5" | "error" => LevelFilter::Error,
-        "0" | "off" | "disable" | "none" => LevelFilter::Off,
-        _ => if cfg!(debug_assertions) {
-            LevelFilter::Trace
-        } else {
-            LevelFilter::Info
+/// Returned by [`init`]. Shuts the logging thread down when dropped or when [`LoggerGuard::shutdown`] is called,
+/// making sure every queued message is written before the program exits.
+#[must_use = "dropping the guard immediately shuts down the logging thread"]
+pub struct LoggerGuard {
+    shared: Arc<Shared>,
+}
+
+impl LoggerGuard {
+    /// A handle to change the configuration of the installed logger, see [`LogHandle`].
+    pub fn handle(&self) -> LogHandle {
+        LogHandle::new(self.shared.clone())
+    }
+
+    /// Write all queued messages, flush the output and join the logging thread.
+    /// Messages logged after this are written synchronously.
+    pub fn shutdown(self) {
+        // the actual work happens in `Drop`
+    }
+}
+
+impl Drop for LoggerGuard {
+    fn drop(&mut self) {
+        self.shared.worker.shutdown();
+    }
+}
+
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Another logger has already been installed with the `log` crate.
+    AlreadySet(log::SetLoggerError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AlreadySet(error) => write!(f, "Could not set logger: {error}"),
         }
-    };
-    
-    let mut logger = BioLogger::new();
-    logger.whitelist_module(&crate_name);   // Auto-whitelist the crate
-    logger.whitelist_module("rocket");  // rocket logs are decent
-    log::set_boxed_logger(Box::new(logger)).expect("Failed to set boxed logger");
-    log::set_max_level(level_filter);
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::AlreadySet(error) => Some(error),
+        }
+    }
 }
 
+
+/// Initialize the logger. This function should be called once at the start of your main function.
+/// 
+/// Keep the returned guard alive until the end of `main`; dropping it flushes and stops the logging thread.
+/// Use [`BioLogger::builder`] for more control over the configuration.
+///
+/// This also installs the logger's panic banner as the panic hook. A panic hook installed before,
+/// e.g. by a crash reporter, is still called after the banner.
+///
+/// Example use: `let logger = biologischer_log::init(env!("CARGO_CRATE_NAME"));`
+pub fn init(crate_name: &str) -> LoggerGuard {
+    BioLogger::builder()
+        .whitelist_module(crate_name)   // Auto-whitelist the crate
+        .whitelist_module("rocket")     // rocket logs are decent
+        .init()
+}
@@ -0,0 +1,196 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+use log::{Level, LevelFilter};
+use crate::Entry;
+use crate::color::{ColorChoice, Painter, Stream};
+use crate::file::FileOutput;
+use crate::format::{Context, Format, Template};
+use crate::timestamp::Timestamp;
+
+/// Where log lines are written to.
+pub enum Output {
+    Stdout,
+    Stderr,
+    /// Records at the given level or more severe go to stderr, everything else to stdout.
+    /// `Output::Split(Level::Warn)` keeps stdout free for the program's actual output.
+    Split(Level),
+    /// A file without colors, optionally rotated.
+    File(FileOutput),
+    /// Any writer, e.g. a socket or an in-memory buffer in tests.
+    Writer(Box<dyn Write + Send>),
+}
+
+/// One destination for log records with its own level and formatting.
+/// Settings that aren't set on the sink itself are taken from the [`Builder`](crate::Builder).
+///
+/// Example use:
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use biologischer_log::{BioLogger, FileOutput, Output, Sink};
+/// use log::{Level, LevelFilter};
+///
+/// let logger = BioLogger::builder()
+///     .sink(Sink::new(Output::Stdout).level(LevelFilter::Info))
+///     .sink(Sink::new(Output::File(FileOutput::open("app.log")?)))
+///     .sink(Sink::new(Output::Stderr).level(LevelFilter::Error))
+///     .init();
+/// # Ok(())
+/// # }
+/// ```
+pub struct Sink {
+    output: Output,
+    level: LevelFilter,
+    color: Option<ColorChoice>,
+    timestamp: Option<Timestamp>,
+    format: Option<Format>,
+    /// When the previous record was written, for [`Timestamp::Delta`].
+    previous: Option<Instant>,
+    /// Resolved from `color` once the sink is built; the stderr one is only used by [`Output::Split`].
+    painter: Painter,
+    stderr_painter: Painter,
+}
+
+impl Sink {
+    /// A sink receiving every record the logger lets through.
+    pub fn new(output: Output) -> Self {
+        Sink {
+            output,
+            level: LevelFilter::Trace,
+            color: None,
+            timestamp: None,
+            format: None,
+            previous: None,
+            painter: Painter(false),
+            stderr_painter: Painter(false),
+        }
+    }
+
+    /// Only write records up to this level to this sink.
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Set how timestamps are written, e.g. [`Timestamp::Utc`] or [`Timestamp::Elapsed`].
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Write the local time with this `chrono` strftime pattern, short for [`Timestamp::Local`].
+    pub fn timestamp_format(self, format: &str) -> Self {
+        self.timestamp(Timestamp::Local(format.to_string()))
+    }
+
+    /// Set how records are turned into lines, e.g. [`Format::Json`].
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Write human-readable lines laid out by this template, see [`Template`].
+    pub fn template(self, template: Template) -> Self {
+        self.format(Format::Text(template))
+    }
+
+    /// Fill in the settings left unset with the builder's defaults and decide whether to use colors.
+    /// Has to happen before the sink writes anything.
+    pub(crate) fn resolve(&mut self, color: ColorChoice, timestamp: &Timestamp, format: &Format) {
+        let color: ColorChoice = *self.color.get_or_insert(color);
+        self.timestamp.get_or_insert_with(|| timestamp.clone());
+        let format: &Format = self.format.get_or_insert_with(|| format.clone());
+
+        let stream: Stream = match self.output {
+            Output::Stdout | Output::Split(_) => Stream::Stdout,
+            Output::Stderr => Stream::Stderr,
+            Output::File(_) | Output::Writer(_) => Stream::Other,
+        };
+        // files and structured formats are meant to be read with other tools, so they never get escape codes
+        let plain: bool = matches!(self.output, Output::File(_)) || !matches!(format, Format::Text(_));
+        self.painter = Painter(!plain && color.enabled_for(stream));
+        self.stderr_painter = Painter(!plain && color.enabled_for(Stream::Stderr));
+    }
+
+    /// Whether this sink writes human-readable lines to the terminal.
+    fn is_console_text(&self) -> bool {
+        matches!(self.output, Output::Stdout | Output::Stderr | Output::Split(_)) && matches!(self.format, Some(Format::Text(_)))
+    }
+
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        self.level
+    }
+
+    pub(crate) fn write(&mut self, entry: &Entry) {
+        if entry.level > self.level {
+            return;
+        }
+        // the panic banner on stderr already shows it, so it would appear twice
+        if entry.panic && self.is_console_text() {
+            return;
+        }
+        let painter: Painter = match self.output {
+            Output::Split(level) if entry.level <= level => self.stderr_painter,
+            _ => self.painter,
+        };
+        let since_previous: Duration = self.previous
+            .map_or(Duration::ZERO, |previous| entry.instant.saturating_duration_since(previous));
+        self.previous = Some(entry.instant);
+
+        let context = Context {
+            painter,
+            timestamp: self.timestamp.as_ref().expect("Sink was not resolved"),
+            since_previous,
+        };
+        let line: String = self.format.as_ref().expect("Sink was not resolved").render(entry, &context);
+
+        // a failing output must never take the program down with it
+        match &mut self.output {
+            Output::Stdout => { writeln!(std::io::stdout().lock(), "{line}").ok(); }
+            Output::Stderr => { writeln!(std::io::stderr().lock(), "{line}").ok(); }
+            Output::Split(level) if entry.level <= *level => { writeln!(std::io::stderr().lock(), "{line}").ok(); }
+            Output::Split(_) => { writeln!(std::io::stdout().lock(), "{line}").ok(); }
+            Output::File(file) => file.write_line(&line),
+            Output::Writer(writer) => { writeln!(writer, "{line}").ok(); }
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        match &mut self.output {
+            Output::Stdout => { std::io::stdout().flush().ok(); }
+            Output::Stderr => { std::io::stderr().flush().ok(); }
+            Output::Split(_) => {
+                std::io::stdout().flush().ok();
+                std::io::stderr().flush().ok();
+            }
+            Output::File(file) => file.flush(),
+            Output::Writer(writer) => { writer.flush().ok(); }
+        }
+    }
+}
+
+/// All sinks of a logger; every entry is offered to each of them.
+pub(crate) struct Sinks(pub(crate) Vec<Sink>);
+
+impl Sinks {
+    pub(crate) fn write(&mut self, entry: &Entry) {
+        for sink in &mut self.0 {
+            sink.write(entry);
+        }
+    }
+
+    pub(crate) fn flush(&mut self) {
+        for sink in &mut self.0 {
+            sink.flush();
+        }
+    }
+
+    /// The most verbose level any sink accepts.
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        self.0.iter().map(Sink::max_level).max().unwrap_or(LevelFilter::Off)
+    }
+}
@@ -0,0 +1,241 @@
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::io::Write;
+use std::panic::PanicHookInfo;
+use std::sync::{Arc, Weak};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+use log::{Level, LevelFilter};
+use colored::Color;
+use crate::{thread_id, Entry};
+use crate::color::Painter;
+use crate::filter::{is_in_module, Filter};
+use crate::handle::{Config, Shared};
+
+/// How long the panic hook waits for the logging thread to write the queued messages.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long the panic hook waits for a sink that is busy on another thread.
+const SINK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Frames from these crates are the panic machinery or the runtime, not the code that panicked.
+const HIDDEN_CRATES: [&str; 5] = ["std", "core", "alloc", "backtrace", "biologischer_log"];
+
+/// What the panic hook does besides printing the banner.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PanicSettings {
+    pub(crate) color: bool,
+    /// Call the hook that was installed before ours after printing the banner, unless that's the standard hook.
+    pub(crate) chain: bool,
+    /// Write the panic as an Error record to every sink.
+    pub(crate) log: bool,
+}
+
+/// One frame of a backtrace that is worth showing.
+struct Frame {
+    symbol: String,
+    /// `file:line:column`, if debug info is available.
+    location: Option<String>,
+}
+
+/// Replace the panic hook. Backtrace frames from modules the logger lets through are highlighted.
+/// The logger is only borrowed weakly so a dropped one isn't kept alive.
+pub(crate) fn install(settings: PanicSettings, shared: Weak<Shared>) {
+    let previous = std::panic::take_hook();
+    // once taken, the standard hook is what's left; calling that one would report the panic a second time
+    let standard = std::panic::take_hook();
+    let chain: bool = settings.chain && !std::ptr::eq(&*previous, &*standard);
+    let painter = Painter(settings.color);
+    std::panic::set_hook(Box::new(move |info| {
+        let message: &str = payload_message(info);
+        let thread: Thread = std::thread::current();
+        let thread_name: String = match thread.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:?}", thread.id()),
+        };
+        // honors RUST_LIB_BACKTRACE and RUST_BACKTRACE
+        let backtrace = Backtrace::capture();
+        let frames: Option<(Vec<Frame>, usize)> = match backtrace.status() {
+            BacktraceStatus::Captured => Some(parse_frames(&backtrace.to_string())),
+            _ => None,
+        };
+
+        let shared: Option<Arc<Shared>> = shared.upgrade();
+        if let Some(shared) = &shared {
+            // the log leading up to the panic has to appear before the banner
+            shared.worker.flush(Some(QUEUE_TIMEOUT));
+            if settings.log {
+                let mut entry: Entry = panic_entry(info, message, &thread);
+                entry.backtrace = frames.as_ref().map(|(frames, _)| frames.iter().map(Frame::to_string).collect());
+                shared.worker.write_sync_timeout(&entry, SINK_TIMEOUT);
+            }
+        }
+
+        let location = info.location().map(|l| {
+            format!("{}:{}:{}", l.file(), l.line(), l.column())
+        }).unwrap_or_else(|| "<unknown>".to_string());
+
+        // Direct write to stderr
+        let bullet = painter.paint(">", Color::Red);
+        let line1 = painter.paint("========== Rust panicked! ==========", Color::Red);
+        let line2 = format!("{} {} {}", bullet, painter.paint("Thread:", Color::BrightRed), painter.paint(&thread_name, Color::BrightYellow));
+        let line3 = format!("{} {} {}", bullet, painter.paint("Location:", Color::BrightRed), painter.paint(&location, Color::BrightYellow));
+        let line4 = format!("{} {} {}", bullet, painter.paint("Message:", Color::BrightRed), painter.paint(message, Color::BrightYellow));
+        let config: Option<Arc<Config>> = shared.map(|shared| shared.config.load_full());
+        let line5 = render_backtrace(painter, config.as_ref().map(|config| &config.filter), backtrace.status(), frames.as_ref());
+        let output = format!("{line1}\n{line2}\n{line3}\n{line4}\n{bullet} {line5}");
+        eprintln!("{output}");
+        std::io::stderr().flush().ok();
+        std::io::stdout().flush().ok();
+
+        if chain {
+            previous(info);
+        }
+    }));
+}
+
+/// Handle both &str and String payload types.
+fn payload_message<'a>(info: &'a PanicHookInfo) -> &'a str {
+    if let Some(s) = info.payload().downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "<panic>"
+    }
+}
+
+fn panic_entry(info: &PanicHookInfo, message: &str, thread: &Thread) -> Entry {
+    Entry {
+        time: chrono::Local::now(),
+        instant: Instant::now(),
+        level: Level::Error,
+        target: "panic".to_string(),
+        module_path: None,
+        file: info.location().map(|location| location.file().to_string()),
+        line: info.location().map(|location| location.line()),
+        thread_name: thread.name().map(str::to_string),
+        thread_id: thread_id(),
+        message: format!("Rust panicked: {message}"),
+        key_values: Vec::new(),
+        backtrace: None,
+        panic: true,
+    }
+}
+
+/// Parse the frames out of `Backtrace`'s `Display` output, since the frames themselves aren't public.
+/// Returns the frames worth showing and how many were hidden.
+///
+/// ```text
+///    9: mycrate::main::{{closure}}
+///              at ./src/main.rs:3:24
+/// ```
+fn parse_frames(backtrace: &str) -> (Vec<Frame>, usize) {
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in backtrace.lines() {
+        let line: &str = line.trim();
+        if let Some(location) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut() {
+                frame.location = Some(location.to_string());
+            }
+        } else if let Some((index, symbol)) = line.split_once(": ") && index.chars().all(|char| char.is_ascii_digit()) {
+            frames.push(Frame { symbol: symbol.to_string(), location: None });
+        }
+    }
+    let total: usize = frames.len();
+    frames.retain(|frame| !frame.is_hidden());
+    let hidden: usize = total - frames.len();
+    (frames, hidden)
+}
+
+impl Frame {
+    /// The symbol without the `<`, `&` or `dyn` in front of trait implementations like `<mycrate::Foo as Display>::fmt`.
+    fn path(&self) -> &str {
+        self.symbol
+            .trim_start_matches(['<', '&'])
+            .trim_start_matches("mut ")
+            .trim_start_matches("dyn ")
+    }
+
+    /// Frames of the standard library, the panic machinery and the runtime around `main`.
+    fn is_hidden(&self) -> bool {
+        let path: &str = self.path();
+        if HIDDEN_CRATES.iter().any(|krate| is_in_module(path, krate)) || path.starts_with("__rust") || path.starts_with("rust_") {
+            return true;
+        }
+        // C runtime frames such as `_start` or `__libc_start_main` have no Rust path and no debug info
+        !path.contains("::") && self.location.is_none()
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{} at {location}", self.symbol),
+            None => f.write_str(&self.symbol),
+        }
+    }
+}
+
+fn render_backtrace(painter: Painter, filter: Option<&Filter>, status: BacktraceStatus, frames: Option<&(Vec<Frame>, usize)>) -> String {
+    let title = painter.paint("Backtrace:", Color::BrightRed);
+    let Some((frames, hidden)) = frames else {
+        let hint: &str = match status {
+            BacktraceStatus::Disabled => "run with `RUST_BACKTRACE=1` to capture one",
+            _ => "not supported on this platform",
+        };
+        return format!("{title} {}\n", painter.dim(hint));
+    };
+
+    let mut output: String = format!("{title}\n");
+    for frame in frames {
+        let whitelisted: bool = filter.is_some_and(|filter| filter.level_for(frame.path()) != LevelFilter::Off);
+        let symbol = match whitelisted {
+            true => painter.paint(&frame.symbol, Color::BrightYellow),
+            false => painter.paint(&frame.symbol, Color::White),
+        };
+        output.push_str(&format!("    {symbol}\n"));
+        if let Some(location) = &frame.location {
+            let location = match whitelisted {
+                true => painter.paint(location, Color::Yellow),
+                false => painter.dim(location),
+            };
+            output.push_str(&format!("        at {location}\n"));
+        }
+    }
+    if *hidden > 0 {
+        output.push_str(&format!("    {}\n", painter.dim(&format!("({hidden} frames of std, core and the panic machinery hidden)"))));
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hidden_frames() {
+        let backtrace: &str = "\
+   0: __rustc::rust_begin_unwind
+             at /rustc/abc/library/std/src/panicking.rs:697:5
+   1: core::panicking::panic_fmt
+             at /rustc/abc/library/core/src/panicking.rs:75:14
+   2: <alloc::boxed::Box<F,A> as core::ops::function::Fn<Args>>::call
+             at /rustc/abc/library/alloc/src/boxed.rs:1985:9
+   3: <mycrate::Sound as core::fmt::Display>::fmt
+             at ./src/sound.rs:12:9
+   4: mycrate::main::{{closure}}
+             at ./src/main.rs:3:24
+   5: __rust_try
+   6: main
+   7: __libc_start_main
+   8: _start
+";
+        let (frames, hidden) = parse_frames(backtrace);
+        let shown: Vec<String> = frames.iter().map(Frame::to_string).collect();
+        assert_eq!(shown, [
+            "<mycrate::Sound as core::fmt::Display>::fmt at ./src/sound.rs:12:9",
+            "mycrate::main::{{closure}} at ./src/main.rs:3:24",
+        ]);
+        assert_eq!(hidden, 7);
+    }
+}
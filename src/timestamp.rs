@@ -0,0 +1,84 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crate::Entry;
+
+pub(crate) const DEFAULT_TIMESTAMP_FORMAT: &str = "%H:%M:%S%.3f";
+
+/// When the first logger was built, for [`Timestamp::Elapsed`].
+static START: OnceLock<Instant> = OnceLock::new();
+
+pub(crate) fn mark_start() {
+    START.get_or_init(Instant::now);
+}
+
+/// How the `{time}` field of a [`Template`](crate::Template) is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timestamp {
+    /// Local wall-clock time with a `chrono` strftime pattern, e.g. `%H:%M:%S%.3f`.
+    Local(String),
+    /// UTC wall-clock time with a `chrono` strftime pattern.
+    Utc(String),
+    /// Local date and time with the UTC offset, e.g. `2025-04-02T23:14:52.826+02:00`.
+    Rfc3339,
+    /// Monotonic time since the logger was initialized, e.g. `12.345s`.
+    Elapsed,
+    /// Time since the previous record written to the same sink, e.g. `+0.002s`.
+    Delta,
+    /// No timestamp at all, e.g. under systemd, which adds its own.
+    None,
+}
+
+impl Default for Timestamp {
+    fn default() -> Self {
+        Timestamp::Local(DEFAULT_TIMESTAMP_FORMAT.to_string())
+    }
+}
+
+impl Timestamp {
+    /// `since_previous` is only used by [`Timestamp::Delta`].
+    pub(crate) fn render(&self, entry: &Entry, since_previous: Duration) -> Option<String> {
+        match self {
+            Timestamp::Local(pattern) => Some(format_time(&entry.time, pattern)),
+            Timestamp::Utc(pattern) => Some(format_time(&entry.time.with_timezone(&Utc), pattern)),
+            Timestamp::Rfc3339 => Some(entry.time.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            Timestamp::Elapsed => {
+                let start: Instant = START.get().copied().unwrap_or(entry.instant);
+                Some(format!("{:.3}s", entry.instant.saturating_duration_since(start).as_secs_f64()))
+            }
+            Timestamp::Delta => Some(format!("+{:.3}s", since_previous.as_secs_f64())),
+            Timestamp::None => None,
+        }
+    }
+
+    /// Render a custom strftime pattern in this timestamp's time zone, or nothing for [`Timestamp::None`].
+    pub(crate) fn render_pattern(&self, entry: &Entry, pattern: &str) -> Option<String> {
+        match self {
+            Timestamp::Utc(_) => Some(format_time(&entry.time.with_timezone(&Utc), pattern)),
+            Timestamp::None => None,
+            _ => Some(format_time(&entry.time, pattern)),
+        }
+    }
+
+    /// The RFC 3339 timestamp of structured formats, which stay absolute regardless of the mode.
+    pub(crate) fn render_rfc3339(&self, entry: &Entry) -> Option<String> {
+        match self {
+            Timestamp::Utc(_) => Some(entry.time.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Millis, true)),
+            Timestamp::None => None,
+            _ => Some(entry.time.to_rfc3339_opts(SecondsFormat::Millis, false)),
+        }
+    }
+}
+
+/// Like `time.format(pattern).to_string()`, but an invalid pattern doesn't panic.
+fn format_time<Tz: chrono::TimeZone>(time: &DateTime<Tz>, pattern: &str) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let mut formatted = String::new();
+    match write!(formatted, "{}", time.format(pattern)) {
+        Ok(()) => formatted,
+        Err(_) => format!("<invalid timestamp format `{pattern}`>"),
+    }
+}
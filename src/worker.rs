@@ -0,0 +1,459 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::Level;
+use crate::Entry;
+use crate::output::Sinks;
+
+/// Maximum number of records waiting for the worker thread before the [`Overflow`] policy kicks in.
+pub(crate) const QUEUE_CAPACITY: usize = 4096;
+
+/// How often the worker reports messages that were dropped because the queue was full.
+const DROP_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What `log()` does when the queue to the logging thread is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Wait until the logging thread has made room. Nothing is lost, but the caller stalls.
+    #[default]
+    Block,
+    /// Discard the message that was just logged.
+    DropNewest,
+    /// Discard the oldest queued message to make room for the new one.
+    DropOldest,
+    /// Write the message directly from the calling thread, bypassing the queue.
+    /// Nothing is lost, but the message may appear before older queued ones.
+    WriteSync,
+}
+
+struct QueueState {
+    entries: VecDeque<Entry>,
+    dropped: u64,
+    closed: bool,
+    /// Set while the worker writes a batch it took from the queue.
+    writing: bool,
+    /// Set once the worker thread has exited; nothing queued afterwards gets written.
+    finished: bool,
+}
+
+/// Bounded queue between the threads calling `log()` and the worker thread writing the records.
+pub(crate) struct Queue {
+    state: Mutex<QueueState>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    /// Signalled whenever the worker finished writing a batch.
+    written: Condvar,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                entries: VecDeque::with_capacity(capacity),
+                dropped: 0,
+                closed: false,
+                writing: false,
+                finished: false,
+            }),
+            capacity,
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            written: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().expect("Could not lock log queue")
+    }
+
+    /// Enqueue an entry, handling a full queue according to `overflow`.
+    /// Hands the entry back if the caller has to write it itself,
+    /// either because the queue has already been closed or because of [`Overflow::WriteSync`].
+    pub(crate) fn push(&self, entry: Entry, overflow: Overflow) -> Option<Entry> {
+        let mut state: MutexGuard<QueueState> = self.lock();
+        if state.entries.len() >= self.capacity && !state.closed {
+            match overflow {
+                Overflow::Block => {
+                    while state.entries.len() >= self.capacity && !state.closed {
+                        state = self.not_full.wait(state).expect("Could not lock log queue");
+                    }
+                }
+                Overflow::DropNewest => {
+                    state.dropped += 1;
+                    return None;
+                }
+                Overflow::DropOldest => {
+                    state.entries.pop_front();
+                    state.dropped += 1;
+                }
+                Overflow::WriteSync => return Some(entry),
+            }
+        }
+        if state.closed {
+            return Some(entry);
+        }
+        state.entries.push_back(entry);
+        drop(state);
+        self.not_empty.notify_one();
+        None
+    }
+
+    /// Block until entries are available and take all of them.
+    /// While dropped messages are waiting to be reported, this wakes up periodically with an empty batch.
+    /// Returns `None` once the queue is closed and fully drained.
+    fn pop_all(&self) -> Option<Vec<Entry>> {
+        let mut state: MutexGuard<QueueState> = self.lock();
+        while state.entries.is_empty() && !state.closed {
+            if state.dropped == 0 {
+                state = self.not_empty.wait(state).expect("Could not lock log queue");
+                continue;
+            }
+            let (new_state, timeout) = self.not_empty
+                .wait_timeout(state, DROP_REPORT_INTERVAL)
+                .expect("Could not lock log queue");
+            state = new_state;
+            if timeout.timed_out() {
+                break;
+            }
+        }
+        if state.entries.is_empty() && state.closed {
+            return None;
+        }
+        let entries: Vec<Entry> = state.entries.drain(..).collect();
+        state.writing = true;
+        drop(state);
+        self.not_full.notify_all();
+        Some(entries)
+    }
+
+    /// Called by the worker once a batch from [`Queue::pop_all`] has been written and flushed.
+    fn finish_batch(&self) {
+        self.lock().writing = false;
+        self.written.notify_all();
+    }
+
+    /// Block until everything queued so far has been written, or until `timeout` has passed.
+    /// Returns whether the queue was emptied in time; gives up right away once the worker has exited.
+    fn wait_until_written(&self, timeout: Option<Duration>) -> bool {
+        let pending = |state: &mut QueueState| !state.finished && (state.writing || !state.entries.is_empty());
+        let state: MutexGuard<QueueState> = self.lock();
+        let state: MutexGuard<QueueState> = match timeout {
+            Some(timeout) => self.written.wait_timeout_while(state, timeout, pending).expect("Could not lock log queue").0,
+            None => self.written.wait_while(state, pending).expect("Could not lock log queue"),
+        };
+        state.entries.is_empty() && !state.writing
+    }
+
+    /// Called when the worker thread exits, however it does. Entries pushed afterwards are handed back to the caller.
+    fn finish(&self) {
+        // this may run while unwinding, where another panic would abort
+        let mut state: MutexGuard<QueueState> = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.closed = true;
+        state.finished = true;
+        state.writing = false;
+        drop(state);
+        self.not_full.notify_all();
+        self.written.notify_all();
+    }
+
+    fn take_dropped(&self) -> u64 {
+        std::mem::take(&mut self.lock().dropped)
+    }
+
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+}
+
+/// Owns the thread writing queued records; shared between the logger and its guard.
+pub(crate) struct LogWorker {
+    pub(crate) queue: Arc<Queue>,
+    sinks: Arc<Mutex<Sinks>>,
+    handle: Mutex<Option<thread::JoinHandle<()>>>,
+    thread_id: thread::ThreadId,
+}
+
+impl LogWorker {
+    pub(crate) fn spawn(capacity: usize, sinks: Sinks) -> Self {
+        let queue: Arc<Queue> = Arc::new(Queue::new(capacity));
+        let sinks: Arc<Mutex<Sinks>> = Arc::new(Mutex::new(sinks));
+        let thread_queue: Arc<Queue> = queue.clone();
+        let thread_sinks: Arc<Mutex<Sinks>> = sinks.clone();
+        let handle = thread::Builder::new()
+            .name("biologischer-log".to_string())
+            .spawn(move || {
+                let _exit = FinishOnExit(&thread_queue);
+                let mut last_report: Instant = Instant::now();
+                while let Some(entries) = thread_queue.pop_all() {
+                    let mut sinks: MutexGuard<Sinks> = lock_sinks(&thread_sinks);
+                    // a panicking sink loses the rest of its batch, but must not take the thread down with it;
+                    // the panic hook has already reported it
+                    panic::catch_unwind(AssertUnwindSafe(|| {
+                        for entry in entries {
+                            sinks.write(&entry);
+                        }
+                        if last_report.elapsed() >= DROP_REPORT_INTERVAL {
+                            last_report = Instant::now();
+                            report_dropped(&mut sinks, thread_queue.take_dropped());
+                        }
+                        sinks.flush();
+                    })).ok();
+                    drop(sinks);
+                    thread_queue.finish_batch();
+                }
+                // don't lose the count of whatever was dropped right before shutting down
+                let mut sinks: MutexGuard<Sinks> = lock_sinks(&thread_sinks);
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    report_dropped(&mut sinks, thread_queue.take_dropped());
+                    sinks.flush();
+                })).ok();
+            })
+            .expect("Could not spawn log worker thread");
+
+        LogWorker {
+            queue,
+            sinks,
+            thread_id: handle.thread().id(),
+            handle: Mutex::new(Some(handle)),
+        }
+    }
+
+    /// Hand an entry to the thread, writing it directly if the queue refuses it.
+    /// On the worker thread itself, e.g. from a sink that logs, the entry is dropped instead:
+    /// waiting for room or for the sinks would wait for the worker.
+    pub(crate) fn write(&self, entry: Entry, overflow: Overflow) {
+        if thread::current().id() == self.thread_id {
+            self.queue.push(entry, Overflow::DropNewest);
+            return;
+        }
+        if let Some(entry) = self.queue.push(entry, overflow) {
+            self.write_sync(&entry);
+        }
+    }
+
+    /// Write an entry from the calling thread, bypassing the queue.
+    pub(crate) fn write_sync(&self, entry: &Entry) {
+        let mut sinks: MutexGuard<Sinks> = lock_sinks(&self.sinks);
+        sinks.write(entry);
+        sinks.flush();
+    }
+
+    /// Like [`LogWorker::write_sync`], but gives up if the sinks stay locked for longer than `timeout`,
+    /// e.g. because the calling thread panicked while writing to them. Returns whether the entry was written.
+    pub(crate) fn write_sync_timeout(&self, entry: &Entry, timeout: Duration) -> bool {
+        // the worker only panics while writing, so it is the one holding the sinks
+        if thread::current().id() == self.thread_id {
+            return false;
+        }
+        let deadline: Instant = Instant::now() + timeout;
+        loop {
+            let mut sinks: MutexGuard<Sinks> = match self.sinks.try_lock() {
+                Ok(sinks) => sinks,
+                // a sink that panicked before is still worth a try
+                Err(TryLockError::Poisoned(error)) => error.into_inner(),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                Err(TryLockError::WouldBlock) => return false,
+            };
+            sinks.write(entry);
+            sinks.flush();
+            return true;
+        }
+    }
+
+    /// Swap the sinks, flushing and closing the old ones.
+    pub(crate) fn replace_sinks(&self, sinks: Sinks) {
+        let mut current: MutexGuard<Sinks> = lock_sinks(&self.sinks);
+        current.flush();
+        *current = sinks;
+    }
+
+    /// Wait until the worker has written and flushed every entry queued so far, giving up after `timeout`.
+    /// Returns immediately when called from the worker thread itself, which would wait for itself.
+    pub(crate) fn flush(&self, timeout: Option<Duration>) -> bool {
+        if thread::current().id() == self.thread_id {
+            return false;
+        }
+        // the worker flushes the sinks after every batch
+        self.queue.wait_until_written(timeout)
+    }
+
+    /// Whether [`LogWorker::shutdown`] has been called or the thread has exited.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.queue.lock().closed
+    }
+
+    /// Close the queue, let the thread write everything still queued and wait for it to exit.
+    /// Records logged afterwards are written synchronously by the calling thread.
+    pub(crate) fn shutdown(&self) {
+        self.queue.close();
+        let handle = self.handle.lock().expect("Could not lock log worker").take();
+        if let Some(handle) = handle {
+            // joining our own thread would deadlock
+            if handle.thread().id() != thread::current().id() {
+                handle.join().ok();
+            }
+        }
+        lock_sinks(&self.sinks).flush();
+    }
+}
+
+/// Marks the queue as finished when the worker thread exits, even by panicking,
+/// so nobody waits for it forever.
+struct FinishOnExit<'a>(&'a Queue);
+
+impl Drop for FinishOnExit<'_> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+/// Lock the sinks even if a sink panicked while another thread was writing to it;
+/// the sinks themselves are still usable, and logging must not stop because of one bad record.
+fn lock_sinks(sinks: &Mutex<Sinks>) -> MutexGuard<'_, Sinks> {
+    sinks.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn report_dropped(sinks: &mut Sinks, dropped: u64) {
+    if dropped == 0 {
+        return;
+    }
+    let message: String = format!("{dropped} log messages were dropped because the log queue was full");
+    sinks.write(&Entry::internal(Level::Warn, message));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::OnceLock;
+    use crate::{ColorChoice, Format, Output, Sink, Timestamp};
+
+    fn entry(message: &str) -> Entry {
+        Entry::internal(Level::Info, message.to_string())
+    }
+
+    fn messages(queue: &Queue) -> Vec<String> {
+        queue.lock().entries.iter().map(|entry| entry.message.clone()).collect()
+    }
+
+    /// Collects everything written to a sink.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Logs through the worker whenever it writes a line containing `outer`.
+    struct LoggingWriter(Arc<OnceLock<Arc<LogWorker>>>);
+
+    impl Write for LoggingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.windows(5).any(|window| window == b"outer") && let Some(worker) = self.0.get() {
+                for _ in 0..3 {
+                    worker.write(entry("inner"), Overflow::Block);
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sink(output: Output) -> Sink {
+        let mut sink: Sink = Sink::new(output);
+        sink.resolve(ColorChoice::Never, &Timestamp::None, &Format::default());
+        sink
+    }
+
+    #[test]
+    fn drop_newest() {
+        let queue = Queue::new(2);
+        for message in ["a", "b", "c", "d"] {
+            assert!(queue.push(entry(message), Overflow::DropNewest).is_none());
+        }
+        assert_eq!(messages(&queue), ["a", "b"]);
+        assert_eq!(queue.take_dropped(), 2);
+        assert_eq!(queue.take_dropped(), 0);
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let queue = Queue::new(2);
+        for message in ["a", "b", "c", "d"] {
+            assert!(queue.push(entry(message), Overflow::DropOldest).is_none());
+        }
+        assert_eq!(messages(&queue), ["c", "d"]);
+        assert_eq!(queue.take_dropped(), 2);
+    }
+
+    #[test]
+    fn write_sync_hands_back_entries() {
+        let queue = Queue::new(1);
+        assert!(queue.push(entry("a"), Overflow::WriteSync).is_none());
+        let entry: Entry = queue.push(entry("b"), Overflow::WriteSync).expect("queue is full");
+        assert_eq!(entry.message, "b");
+        assert_eq!(messages(&queue), ["a"]);
+        assert_eq!(queue.take_dropped(), 0);
+    }
+
+    #[test]
+    fn dropped_messages_are_reported() {
+        let buffer = Buffer::default();
+        let mut sinks = Sinks(vec![sink(Output::Writer(Box::new(buffer.clone())))]);
+        report_dropped(&mut sinks, 0);
+        assert!(buffer.0.lock().unwrap().is_empty());
+        report_dropped(&mut sinks, 3);
+        let output: String = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("3 log messages were dropped because the log queue was full"), "{output}");
+    }
+
+    #[test]
+    fn sink_logging_on_worker_does_not_block() {
+        let worker_cell: Arc<OnceLock<Arc<LogWorker>>> = Arc::new(OnceLock::new());
+        let sinks = Sinks(vec![sink(Output::Writer(Box::new(LoggingWriter(worker_cell.clone()))))]);
+        let worker: Arc<LogWorker> = Arc::new(LogWorker::spawn(1, sinks));
+        worker_cell.set(worker.clone()).ok();
+        worker.write(entry("outer"), Overflow::Block);
+        assert!(worker.flush(Some(Duration::from_secs(5))));
+        worker.shutdown();
+    }
+
+    #[test]
+    fn flush_returns_once_worker_is_gone() {
+        let queue = Queue::new(4);
+        assert!(queue.push(entry("queued"), Overflow::Block).is_none());
+        queue.finish();
+        assert!(!queue.wait_until_written(None));
+        assert!(!queue.wait_until_written(Some(Duration::from_secs(1))));
+        // later entries are handed back to be written by the caller
+        assert!(queue.push(entry("late"), Overflow::Block).is_some());
+    }
+
+    #[test]
+    fn flush_waits_for_worker() {
+        let worker = LogWorker::spawn(4, Sinks(Vec::new()));
+        worker.write(entry("queued"), Overflow::Block);
+        assert!(worker.flush(None));
+        worker.shutdown();
+        // nothing is left to write, so this must not wait for the thread that is gone
+        assert!(worker.flush(None));
+    }
+}